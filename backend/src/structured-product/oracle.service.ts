@@ -55,6 +55,8 @@ export class OracleService {
       'CRZYBTC',
       this.serverSdk.provider.publicKey,
     );
+    // prices are stored in 10^exponent units of the quote currency
+    const decimals = -oldPrice.exponent;
     const sigmaToPriceRatio = 0.05;
     const sigma = oldPrice.currentPrice.muln(sigmaToPriceRatio).toNumber();
 
@@ -82,10 +84,10 @@ export class OracleService {
 
     this.logger.log(
       {
-        oldPrice: toUiAmount(oldPrice.currentPrice, 9).toString(),
-        mean: toUiAmount(new BN(correctiveMean), 9).toString(),
-        newPriceDelta: toUiAmount(newPriceDelta, 9).toString(),
-        newPrice: toUiAmount(newPrice, 9).toString(),
+        oldPrice: toUiAmount(oldPrice.currentPrice, decimals).toString(),
+        mean: toUiAmount(new BN(correctiveMean), decimals).toString(),
+        newPriceDelta: toUiAmount(newPriceDelta, decimals).toString(),
+        newPrice: toUiAmount(newPrice, decimals).toString(),
       },
       'Updating price',
     );
@@ -100,7 +102,7 @@ export class OracleService {
 
     this.logger.log(
       {
-        newPrice: toUiAmount(newPrice, 9).toString(),
+        newPrice: toUiAmount(newPrice, decimals).toString(),
         txId: updatePriceTxId,
       },
       'Price updated',
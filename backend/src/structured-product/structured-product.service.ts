@@ -267,12 +267,20 @@ export class StructuredProductService implements OnModuleInit {
           dummyOracle: dummyOraclePDA.publicKey,
           underlyingSymbol: 'CRZYBTC',
           paymentMint: this.paymentMint,
+          quoteCurrencyMint: oracleAccount.quoteCurrencyMint,
           initialPrincipal: new BN(principal.toString()),
           initialFixingPrice,
+          priceExponent: oracleAccount.exponent,
           barrierInBasisPoints: new BN(
             Math.round(structuredProductDeployDto.barrierLevel * 100), // convert to basis points
           ),
+          continuousBarrier: false,
+          // prices are updated every few seconds, payments are processed by a queue
+          maxStaleness: new BN(60 * 60),
+          fixingWindow: new BN(60 * 60),
           supply: new BN(supply),
+          offerExpiry: null,
+          legalApprover: null,
         },
         mint,
         nonce1.publicKey,
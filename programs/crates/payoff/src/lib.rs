@@ -0,0 +1,292 @@
+use anchor_lang::prelude::*;
+
+pub const MAX_NODES: usize = 64;
+// borsh size of the largest node, tag and three indices or a u64 constant
+pub const MAX_NODE_SIZE: usize = 9;
+
+// A node of a payoff expression. Operands are indices of earlier nodes, so an expression is
+// evaluated in a single pass and its value is the value of the last node.
+// Conditions evaluate to 1 (true) or 0 (false).
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Node {
+    Const(u64),
+    // fixing price of the underlying at the index
+    Fixing(u8),
+    Add(u8, u8),
+    Sub(u8, u8),
+    // a * b / c rounded down, c has to be positive
+    MulDiv(u8, u8, u8),
+    Min(u8, u8),
+    Max(u8, u8),
+    Lt(u8, u8),
+    Lte(u8, u8),
+    And(u8, u8),
+    Or(u8, u8),
+    // condition, then, else
+    If(u8, u8, u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PayoffError {
+    EmptyExpression,
+    TooManyNodes,
+    InvalidOperand,
+    MissingFixing,
+    DivisionByZero,
+    Overflow,
+    NegativeResult,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Payoff {
+    pub nodes: Vec<Node>,
+}
+
+impl Payoff {
+    pub fn space(num_nodes: usize) -> usize {
+        4 + MAX_NODE_SIZE * num_nodes
+    }
+
+    // Checks that operands only refer to earlier nodes and fixings to one of the num_fixings underlyings
+    pub fn validate(&self, num_fixings: usize) -> std::result::Result<(), PayoffError> {
+        if self.nodes.is_empty() {
+            return Err(PayoffError::EmptyExpression);
+        }
+        if self.nodes.len() > MAX_NODES {
+            return Err(PayoffError::TooManyNodes);
+        }
+
+        for (i, node) in self.nodes.iter().enumerate() {
+            let operands: &[u8] = match node {
+                Node::Const(_) => &[],
+                Node::Fixing(fixing) => {
+                    if *fixing as usize >= num_fixings {
+                        return Err(PayoffError::MissingFixing);
+                    }
+                    &[]
+                }
+                Node::Add(a, b)
+                | Node::Sub(a, b)
+                | Node::Min(a, b)
+                | Node::Max(a, b)
+                | Node::Lt(a, b)
+                | Node::Lte(a, b)
+                | Node::And(a, b)
+                | Node::Or(a, b) => &[*a, *b],
+                Node::MulDiv(a, b, c) | Node::If(a, b, c) => &[*a, *b, *c],
+            };
+            if operands.iter().any(|operand| *operand as usize >= i) {
+                return Err(PayoffError::InvalidOperand);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn evaluate(&self, fixings: &[u64]) -> std::result::Result<u64, PayoffError> {
+        self.validate(fixings.len())?;
+
+        let mut values: Vec<i128> = Vec::with_capacity(self.nodes.len());
+        for node in &self.nodes {
+            let value = |index: &u8| values[*index as usize];
+            let result = match node {
+                Node::Const(constant) => *constant as i128,
+                Node::Fixing(fixing) => fixings[*fixing as usize] as i128,
+                Node::Add(a, b) => value(a)
+                    .checked_add(value(b))
+                    .ok_or(PayoffError::Overflow)?,
+                Node::Sub(a, b) => value(a)
+                    .checked_sub(value(b))
+                    .ok_or(PayoffError::Overflow)?,
+                Node::MulDiv(a, b, c) => {
+                    if value(c) <= 0 {
+                        return Err(PayoffError::DivisionByZero);
+                    }
+                    value(a)
+                        .checked_mul(value(b))
+                        .ok_or(PayoffError::Overflow)?
+                        .div_euclid(value(c))
+                }
+                Node::Min(a, b) => value(a).min(value(b)),
+                Node::Max(a, b) => value(a).max(value(b)),
+                Node::Lt(a, b) => (value(a) < value(b)).into(),
+                Node::Lte(a, b) => (value(a) <= value(b)).into(),
+                Node::And(a, b) => (value(a) != 0 && value(b) != 0).into(),
+                Node::Or(a, b) => (value(a) != 0 || value(b) != 0).into(),
+                Node::If(condition, then, otherwise) => match value(condition) {
+                    0 => value(otherwise),
+                    _ => value(then),
+                },
+            };
+            values.push(result);
+        }
+
+        let result = *values.last().ok_or(PayoffError::EmptyExpression)?;
+        if result < 0 {
+            return Err(PayoffError::NegativeResult);
+        }
+        u64::try_from(result).map_err(|_| PayoffError::Overflow)
+    }
+}
+
+// Appends nodes and returns their index to be used as operand of later nodes
+#[derive(Default)]
+pub struct PayoffBuilder {
+    nodes: Vec<Node>,
+}
+
+impl PayoffBuilder {
+    pub fn push(&mut self, node: Node) -> u8 {
+        self.nodes.push(node);
+        (self.nodes.len() - 1) as u8
+    }
+
+    pub fn constant(&mut self, constant: u64) -> u8 {
+        self.push(Node::Const(constant))
+    }
+
+    pub fn fixing(&mut self, fixing: u8) -> u8 {
+        self.push(Node::Fixing(fixing))
+    }
+
+    // amount * fixing / strike
+    pub fn ratio_to_strike(&mut self, amount: u8, fixing: u8, strike: u8) -> u8 {
+        self.push(Node::MulDiv(amount, fixing, strike))
+    }
+
+    // amount * participation_in_basis_points / 10000
+    pub fn participation(&mut self, amount: u8, participation_in_basis_points: u64) -> u8 {
+        let participation = self.constant(participation_in_basis_points);
+        let basis_points = self.constant(10000);
+        self.push(Node::MulDiv(amount, participation, basis_points))
+    }
+
+    pub fn cap(&mut self, amount: u8, cap: u8) -> u8 {
+        self.push(Node::Min(amount, cap))
+    }
+
+    pub fn floor(&mut self, amount: u8, floor: u8) -> u8 {
+        self.push(Node::Max(amount, floor))
+    }
+
+    pub fn build(self) -> Payoff {
+        Payoff { nodes: self.nodes }
+    }
+}
+
+// Final principal of a barrier reverse convertible on the fixing at index 0,
+// the principal follows the underlying if the final fixing price is at or below the barrier
+pub fn barrier_reverse_convertible(
+    initial_principal: u64,
+    initial_fixing_price: u64,
+    barrier: u64,
+) -> Payoff {
+    let mut builder = PayoffBuilder::default();
+    let principal = builder.constant(initial_principal);
+    let strike = builder.constant(initial_fixing_price);
+    let barrier = builder.constant(barrier);
+    let fixing = builder.fixing(0);
+    let knocked_in = builder.push(Node::Lte(fixing, barrier));
+    let performance = builder.ratio_to_strike(principal, fixing, strike);
+    builder.push(Node::If(knocked_in, performance, principal));
+    builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! evaluate_tests {
+        ($($name:ident: $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (nodes, fixings, expected) = $expected;
+                    let payoff = Payoff { nodes: nodes.to_vec() };
+                    let result = payoff.evaluate(&fixings);
+                    assert_eq!(result, expected);
+                }
+            )*
+        }
+    }
+
+    #[cfg(test)]
+    evaluate_tests! {
+        evaluate_test_1: ([Node::Const(100000)], [], Ok(100000),),
+        evaluate_test_2: ([Node::Fixing(1)], [42000, 100], Ok(100),),
+        evaluate_test_3: ([Node::Fixing(0), Node::Const(1000), Node::Add(0, 1)], [42000], Ok(43000),),
+        evaluate_test_4: ([Node::Fixing(0), Node::Const(1000), Node::Sub(0, 1)], [42000], Ok(41000),),
+        evaluate_test_5: ([Node::Fixing(0), Node::Const(50000), Node::Sub(0, 1)], [42000], Err(PayoffError::NegativeResult),),
+        evaluate_test_6: ([Node::Const(100000), Node::Fixing(0), Node::Const(42000), Node::MulDiv(0, 1, 2)], [30000], Ok(71428),),
+        evaluate_test_7: ([Node::Const(100000), Node::Fixing(0), Node::Const(0), Node::MulDiv(0, 1, 2)], [30000], Err(PayoffError::DivisionByZero),),
+        evaluate_test_8: ([Node::Fixing(0), Node::Fixing(1), Node::Min(0, 1)], [42000, 100], Ok(100),),
+        evaluate_test_9: ([Node::Fixing(0), Node::Fixing(1), Node::Max(0, 1)], [42000, 100], Ok(42000),),
+        evaluate_test_10: ([Node::Fixing(0), Node::Fixing(1), Node::Lt(0, 1)], [100, 100], Ok(0),),
+        evaluate_test_11: ([Node::Fixing(0), Node::Fixing(1), Node::Lte(0, 1)], [100, 100], Ok(1),),
+        evaluate_test_12: ([Node::Const(1), Node::Const(0), Node::And(0, 1)], [], Ok(0),),
+        evaluate_test_13: ([Node::Const(1), Node::Const(0), Node::Or(0, 1)], [], Ok(1),),
+        evaluate_test_14: ([Node::Const(0), Node::Const(5), Node::Const(7), Node::If(0, 1, 2)], [], Ok(7),),
+        evaluate_test_15: ([Node::Const(u64::MAX), Node::Const(u64::MAX), Node::Add(0, 1)], [], Err(PayoffError::Overflow),),
+        evaluate_test_16: ([Node::Const(u64::MAX), Node::Const(2), Node::Const(4), Node::MulDiv(0, 1, 2)], [], Ok(u64::MAX / 2),),
+        evaluate_test_17: ([Node::Fixing(2)], [42000, 100], Err(PayoffError::MissingFixing),),
+        evaluate_test_18: ([Node::Const(1), Node::Add(0, 1)], [], Err(PayoffError::InvalidOperand),),
+        evaluate_test_19: ([Node::Const(1), Node::If(0, 2, 0), Node::Const(2)], [], Err(PayoffError::InvalidOperand),),
+        evaluate_test_20: ([Node::Const(0); 0], [], Err(PayoffError::EmptyExpression),),
+        evaluate_test_21: ([Node::Const(0); MAX_NODES + 1], [], Err(PayoffError::TooManyNodes),),
+        evaluate_test_22: ([Node::Const(u64::MAX), Node::Const(u64::MAX), Node::Const(u64::MAX), Node::MulDiv(0, 1, 2)], [], Err(PayoffError::Overflow),),
+    }
+
+    #[test]
+    fn payoff_space() {
+        let payoff = barrier_reverse_convertible(u64::MAX, u64::MAX, u64::MAX);
+        let data = payoff.try_to_vec().unwrap();
+        assert!(data.len() <= Payoff::space(payoff.nodes.len()));
+    }
+
+    // Worst of two underlyings with a 70% barrier, capped at the principal
+    #[test]
+    fn evaluate_worst_of() {
+        let mut builder = PayoffBuilder::default();
+        let principal = builder.constant(100000);
+        let strike_1 = builder.constant(42000);
+        let strike_2 = builder.constant(100);
+        let barrier_1 = builder.constant(29400);
+        let barrier_2 = builder.constant(70);
+        let fixing_1 = builder.fixing(0);
+        let fixing_2 = builder.fixing(1);
+        let knocked_in_1 = builder.push(Node::Lte(fixing_1, barrier_1));
+        let knocked_in_2 = builder.push(Node::Lte(fixing_2, barrier_2));
+        let knocked_in = builder.push(Node::Or(knocked_in_1, knocked_in_2));
+        let performance_1 = builder.ratio_to_strike(principal, fixing_1, strike_1);
+        let performance_2 = builder.ratio_to_strike(principal, fixing_2, strike_2);
+        let worst = builder.push(Node::Min(performance_1, performance_2));
+        let worst = builder.cap(worst, principal);
+        builder.push(Node::If(knocked_in, worst, principal));
+        let payoff = builder.build();
+
+        assert_eq!(payoff.evaluate(&[30000, 90]), Ok(100000));
+        assert_eq!(payoff.evaluate(&[29400, 90]), Ok(70000));
+        assert_eq!(payoff.evaluate(&[42000, 50]), Ok(50000));
+        assert_eq!(payoff.evaluate(&[55000, 120]), Ok(100000));
+    }
+
+    #[test]
+    fn evaluate_participation_with_floor() {
+        let mut builder = PayoffBuilder::default();
+        let principal = builder.constant(100000);
+        let strike = builder.constant(42000);
+        let fixing = builder.fixing(0);
+        let performance = builder.ratio_to_strike(principal, fixing, strike);
+        let upside = builder.push(Node::Sub(performance, principal));
+        let upside = builder.participation(upside, 5000);
+        let redemption = builder.push(Node::Add(principal, upside));
+        let floor = builder.participation(principal, 9000);
+        builder.floor(redemption, floor);
+        let payoff = builder.build();
+
+        assert_eq!(payoff.evaluate(&[50400]), Ok(110000));
+        assert_eq!(payoff.evaluate(&[42000]), Ok(100000));
+        assert_eq!(payoff.evaluate(&[37800]), Ok(95000));
+        assert_eq!(payoff.evaluate(&[21000]), Ok(90000));
+    }
+}
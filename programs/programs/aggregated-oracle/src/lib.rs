@@ -0,0 +1,389 @@
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::Mint;
+
+declare_id!("52FUMp9NzKgjTotdMnHhfVFy83CBAtczyr4M2vChCoq4");
+
+pub const MAX_PUBLISHERS: usize = 16;
+
+// Returns the median of the prices and the confidence, half the range of the prices.
+// With an even number of prices the median is the mean of the two middle prices.
+pub fn calc_aggregate_price(prices: &mut [u64]) -> Option<(u64, u64)> {
+    if prices.is_empty() {
+        return None;
+    }
+    prices.sort_unstable();
+
+    let middle = prices.len() / 2;
+    let median = match prices.len() % 2 {
+        0 => ((prices[middle - 1] as u128 + prices[middle] as u128) / 2) as u64,
+        _ => prices[middle],
+    };
+    let confidence = (prices[prices.len() - 1] - prices[0]) / 2;
+
+    Some((median, confidence))
+}
+
+#[error_code]
+pub enum AggregatedOracleError {
+    #[msg("Unauthorized")]
+    Unauthorized,
+    #[msg("Invalid publishers")]
+    InvalidPublishers,
+    #[msg("Invalid quorum")]
+    InvalidQuorum,
+    #[msg("Publisher already registered")]
+    PublisherAlreadyRegistered,
+    #[msg("Publisher not registered")]
+    PublisherNotRegistered,
+    #[msg("Not enough fresh submissions")]
+    QuorumNotReached,
+    #[msg("Invalid max staleness")]
+    InvalidMaxStaleness,
+}
+
+#[program]
+pub mod aggregated_oracle {
+    use super::*;
+
+    pub fn initialize(
+        ctx: Context<Initialize>,
+        asset_symbol: String,
+        publishers: Vec<Pubkey>,
+        quorum: u8,
+        max_staleness: i64,
+        exponent: i32,
+    ) -> Result<()> {
+        require!(
+            !publishers.is_empty()
+                && publishers.len() <= MAX_PUBLISHERS
+                && publishers
+                    .iter()
+                    .enumerate()
+                    .all(|(i, publisher)| !publishers[..i].contains(publisher)),
+            AggregatedOracleError::InvalidPublishers
+        );
+        require!(
+            quorum > 0 && quorum as usize <= publishers.len(),
+            AggregatedOracleError::InvalidQuorum
+        );
+        require!(
+            max_staleness > 0,
+            AggregatedOracleError::InvalidMaxStaleness
+        );
+
+        let aggregated_oracle = &mut ctx.accounts.aggregated_oracle;
+        aggregated_oracle.authority = *ctx.accounts.authority.key;
+        aggregated_oracle.asset_symbol = asset_symbol;
+        aggregated_oracle.quote_currency_mint = ctx.accounts.quote_currency_mint.key();
+        aggregated_oracle.exponent = exponent;
+        aggregated_oracle.quorum = quorum;
+        aggregated_oracle.max_staleness = max_staleness;
+        aggregated_oracle.publishers = publishers
+            .into_iter()
+            .map(|publisher| PublisherSubmission {
+                publisher,
+                price: 0,
+                last_update: 0,
+            })
+            .collect();
+        // set by the first aggregation
+        aggregated_oracle.aggregate_price = 0;
+        aggregated_oracle.confidence = 0;
+        aggregated_oracle.last_update = 0;
+        aggregated_oracle.bump = ctx.bumps.aggregated_oracle;
+
+        Ok(())
+    }
+
+    pub fn add_publisher(ctx: Context<UpdatePublishers>, publisher: Pubkey) -> Result<()> {
+        let aggregated_oracle = &mut ctx.accounts.aggregated_oracle;
+        require!(
+            aggregated_oracle.publishers.len() < MAX_PUBLISHERS,
+            AggregatedOracleError::InvalidPublishers
+        );
+        require!(
+            aggregated_oracle.publisher_index(&publisher).is_none(),
+            AggregatedOracleError::PublisherAlreadyRegistered
+        );
+
+        aggregated_oracle.publishers.push(PublisherSubmission {
+            publisher,
+            price: 0,
+            last_update: 0,
+        });
+
+        Ok(())
+    }
+
+    pub fn remove_publisher(ctx: Context<UpdatePublishers>, publisher: Pubkey) -> Result<()> {
+        let aggregated_oracle = &mut ctx.accounts.aggregated_oracle;
+        let index = aggregated_oracle
+            .publisher_index(&publisher)
+            .ok_or(AggregatedOracleError::PublisherNotRegistered)?;
+        require!(
+            (aggregated_oracle.quorum as usize) < aggregated_oracle.publishers.len(),
+            AggregatedOracleError::InvalidQuorum
+        );
+
+        aggregated_oracle.publishers.remove(index);
+
+        Ok(())
+    }
+
+    pub fn set_quorum(ctx: Context<UpdatePublishers>, quorum: u8) -> Result<()> {
+        let aggregated_oracle = &mut ctx.accounts.aggregated_oracle;
+        require!(
+            quorum > 0 && quorum as usize <= aggregated_oracle.publishers.len(),
+            AggregatedOracleError::InvalidQuorum
+        );
+
+        aggregated_oracle.quorum = quorum;
+
+        Ok(())
+    }
+
+    pub fn submit_price(ctx: Context<SubmitPrice>, price: u64) -> Result<()> {
+        let aggregated_oracle = &mut ctx.accounts.aggregated_oracle;
+        let index = aggregated_oracle
+            .publisher_index(ctx.accounts.publisher.key)
+            .ok_or(AggregatedOracleError::PublisherNotRegistered)?;
+
+        let submission = &mut aggregated_oracle.publishers[index];
+        submission.price = price;
+        submission.last_update = Clock::get()?.unix_timestamp;
+
+        Ok(())
+    }
+
+    // Permissionless, aggregates the fresh submissions once the quorum is reached
+    pub fn aggregate(ctx: Context<Aggregate>) -> Result<()> {
+        let aggregated_oracle = &mut ctx.accounts.aggregated_oracle;
+        let now = Clock::get()?.unix_timestamp;
+
+        let mut prices = aggregated_oracle.fresh_prices(now);
+        require!(
+            prices.len() >= aggregated_oracle.quorum as usize,
+            AggregatedOracleError::QuorumNotReached
+        );
+        let (aggregate_price, confidence) =
+            calc_aggregate_price(&mut prices).ok_or(AggregatedOracleError::QuorumNotReached)?;
+
+        msg!(
+            "Aggregated {} submissions, price: {}, confidence: {}",
+            prices.len(),
+            aggregate_price,
+            confidence
+        );
+
+        aggregated_oracle.aggregate_price = aggregate_price;
+        aggregated_oracle.confidence = confidence;
+        // the aggregate is only as fresh as its oldest contributing submission
+        aggregated_oracle.last_update = aggregated_oracle
+            .oldest_fresh_update(now)
+            .ok_or(AggregatedOracleError::QuorumNotReached)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(asset_symbol: String, publishers: Vec<Pubkey>)]
+pub struct Initialize<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub quote_currency_mint: InterfaceAccount<'info, Mint>,
+    #[account(init,
+    seeds=[authority.key().as_ref(), asset_symbol.as_bytes()], bump,
+    payer=authority, space=AggregatedOracleAccount::space(asset_symbol.len()))]
+    pub aggregated_oracle: Account<'info, AggregatedOracleAccount>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePublishers<'info> {
+    pub authority: Signer<'info>,
+    #[account(mut, has_one=authority @ AggregatedOracleError::Unauthorized)]
+    pub aggregated_oracle: Account<'info, AggregatedOracleAccount>,
+}
+
+#[derive(Accounts)]
+pub struct SubmitPrice<'info> {
+    pub publisher: Signer<'info>,
+    #[account(mut)]
+    pub aggregated_oracle: Account<'info, AggregatedOracleAccount>,
+}
+
+#[derive(Accounts)]
+pub struct Aggregate<'info> {
+    pub payer: Signer<'info>,
+    #[account(mut)]
+    pub aggregated_oracle: Account<'info, AggregatedOracleAccount>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PublisherSubmission {
+    pub publisher: Pubkey,
+    pub price: u64,
+    pub last_update: i64,
+}
+
+// Submitted and aggregated prices are price * 10^exponent units of the quote currency
+#[account]
+pub struct AggregatedOracleAccount {
+    pub authority: Pubkey,
+    pub asset_symbol: String,
+    pub quote_currency_mint: Pubkey,
+    pub exponent: i32,
+    pub quorum: u8,
+    pub max_staleness: i64,
+    pub publishers: Vec<PublisherSubmission>,
+    pub aggregate_price: u64,
+    pub confidence: u64,
+    pub last_update: i64,
+    pub bump: u8,
+}
+
+impl AggregatedOracleAccount {
+    pub fn space(asset_symbol_len_in_bytes: usize) -> usize {
+        8 + 32
+            + 4
+            + asset_symbol_len_in_bytes
+            + 32
+            + 4
+            + 1
+            + 8
+            + 4
+            + 48 * MAX_PUBLISHERS
+            + 8
+            + 8
+            + 8
+            + 1
+    }
+
+    pub fn publisher_index(&self, publisher: &Pubkey) -> Option<usize> {
+        self.publishers
+            .iter()
+            .position(|submission| &submission.publisher == publisher)
+    }
+
+    fn is_fresh(&self, submission: &PublisherSubmission, now: i64) -> bool {
+        submission.last_update > 0 && now - submission.last_update <= self.max_staleness
+    }
+
+    // Prices submitted within max_staleness seconds of now
+    pub fn fresh_prices(&self, now: i64) -> Vec<u64> {
+        self.publishers
+            .iter()
+            .filter(|submission| self.is_fresh(submission, now))
+            .map(|submission| submission.price)
+            .collect()
+    }
+
+    // Submission time of the oldest price submitted within max_staleness seconds of now
+    pub fn oldest_fresh_update(&self, now: i64) -> Option<i64> {
+        self.publishers
+            .iter()
+            .filter(|submission| self.is_fresh(submission, now))
+            .map(|submission| submission.last_update)
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! calc_aggregate_price_tests {
+        ($($name:ident: $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (prices, expected) = $expected;
+                    let mut prices = prices.to_vec();
+                    let result = calc_aggregate_price(&mut prices);
+                    assert_eq!(result, expected);
+                }
+            )*
+        }
+    }
+
+    #[cfg(test)]
+    calc_aggregate_price_tests! {
+        aggregate_price_test_1: ([42000u64], Some((42000, 0)),),
+        aggregate_price_test_2: ([42000u64, 42100, 41900], Some((42000, 100)),),
+        aggregate_price_test_3: ([42100u64, 41900, 42000, 42300], Some((42050, 200)),),
+        aggregate_price_test_4: ([42000u64, 42000, 1], Some((42000, 20999)),),
+        aggregate_price_test_5: ([u64::MAX, u64::MAX], Some((u64::MAX, 0)),),
+        aggregate_price_test_6: ([0u64; 0], None,),
+    }
+
+    fn aggregated_oracle(
+        submissions: &[(u64, i64)],
+        max_staleness: i64,
+    ) -> AggregatedOracleAccount {
+        AggregatedOracleAccount {
+            authority: Pubkey::new_unique(),
+            asset_symbol: "BTC".to_string(),
+            quote_currency_mint: Pubkey::new_unique(),
+            exponent: 0,
+            quorum: 1,
+            max_staleness,
+            publishers: submissions
+                .iter()
+                .map(|(price, last_update)| PublisherSubmission {
+                    publisher: Pubkey::new_unique(),
+                    price: *price,
+                    last_update: *last_update,
+                })
+                .collect(),
+            aggregate_price: 0,
+            confidence: 0,
+            last_update: 0,
+            bump: 255,
+        }
+    }
+
+    macro_rules! fresh_prices_tests {
+        ($($name:ident: $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (submissions, max_staleness, now, expected) = $expected;
+                    let aggregated_oracle = aggregated_oracle(&submissions, max_staleness);
+                    let result = aggregated_oracle.fresh_prices(now);
+                    assert_eq!(result, expected);
+                }
+            )*
+        }
+    }
+
+    #[cfg(test)]
+    fresh_prices_tests! {
+        fresh_prices_test_1: ([(42000, 1000), (42100, 990), (41900, 900)], 60, 1000, vec![42000, 42100],),
+        fresh_prices_test_2: ([(42000, 1000), (42100, 940), (41900, 939)], 60, 1000, vec![42000, 42100],),
+        fresh_prices_test_3: ([(42000, 0), (42100, 0), (41900, 0)], 60, 30, Vec::<u64>::new(),),
+        fresh_prices_test_4: ([(42000, 1000), (42100, 1000), (41900, 1000)], 60, 2000, Vec::<u64>::new(),),
+    }
+
+    macro_rules! oldest_fresh_update_tests {
+        ($($name:ident: $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (submissions, max_staleness, now, expected) = $expected;
+                    let aggregated_oracle = aggregated_oracle(&submissions, max_staleness);
+                    let result = aggregated_oracle.oldest_fresh_update(now);
+                    assert_eq!(result, expected);
+                }
+            )*
+        }
+    }
+
+    #[cfg(test)]
+    oldest_fresh_update_tests! {
+        oldest_fresh_update_test_1: ([(42000, 1000), (42100, 990), (41900, 900)], 60, 1000, Some(990),),
+        oldest_fresh_update_test_2: ([(42000, 1000), (42100, 940), (41900, 939)], 60, 1000, Some(940),),
+        oldest_fresh_update_test_3: ([(42000, 0), (42100, 0), (41900, 0)], 60, 30, None,),
+        oldest_fresh_update_test_4: ([(42000, 1000), (42100, 1000), (41900, 1000)], 60, 2000, None,),
+    }
+}
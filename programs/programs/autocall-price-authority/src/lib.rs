@@ -0,0 +1,325 @@
+use anchor_lang::prelude::*;
+use brc_price_authority::validate_observation_timestamp;
+use dummy_oracle::program::DummyOracle;
+use dummy_oracle::DummyOracleAccount;
+use structured_product::program::StructuredProduct;
+use structured_product::{Payment, StructuredProductConfig};
+
+declare_id!("66zbPMeTXs4bb6EyDiVRXY46zCgcHMdaz6waebKddJjz");
+
+// Returns the redemption amount per unit if the product redeems at this observation,
+// or None if it rolls forward to the next observation.
+// At the final observation the product always redeems, below the barrier the investor takes the loss.
+pub fn calc_observation_payout(
+    initial_principal: u64,
+    initial_fixing_price: u64,
+    autocall_trigger: u64,
+    barrier: u64,
+    coupon_per_unit: u64,
+    fixing_price: u64,
+    final_observation: bool,
+) -> Option<u64> {
+    match fixing_price {
+        fixing_price if fixing_price >= autocall_trigger => {
+            Some(initial_principal + coupon_per_unit)
+        }
+        _ if !final_observation => None,
+        fixing_price if fixing_price <= barrier => Some(
+            ((initial_principal as u128 * fixing_price as u128) / initial_fixing_price as u128)
+                as u64,
+        ),
+        _ => Some(initial_principal),
+    }
+}
+
+#[error_code]
+pub enum AutocallPriceAuthorityError {
+    #[msg("Unauthorized")]
+    Unauthorized,
+    #[msg("Invalid observation date")]
+    InvalidObservationDate,
+    #[msg("All observations done")]
+    ObservationsCompleted,
+    #[msg("Product not issued")]
+    NotIssued,
+}
+
+#[program]
+pub mod autocall_price_authority {
+    use super::*;
+    use structured_product::cpi::accounts::SetPaymentPrice;
+    use structured_product::cpi::set_payment_price;
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn initialize(
+        ctx: Context<Initialize>,
+        underlying_symbol: String,
+        observation_date_offsets: Vec<i64>,
+        initial_principal: u64,
+        initial_fixing_price: u64,
+        autocall_trigger_in_basis_points: u64,
+        barrier_in_basis_points: u64,
+        coupon_per_unit: u64,
+        max_staleness: i64,
+        fixing_window: i64,
+    ) -> Result<()> {
+        // observations are counted in a u8
+        require!(
+            !observation_date_offsets.is_empty()
+                && observation_date_offsets.len() <= u8::MAX as usize
+                && observation_date_offsets.windows(2).all(|w| w[0] < w[1]),
+            AutocallPriceAuthorityError::InvalidObservationDate
+        );
+
+        msg!(
+            "Initializing autocallable at {}",
+            ctx.accounts.autocallable.key()
+        );
+        let autocallable = &mut ctx.accounts.autocallable;
+
+        autocallable.authority = *ctx.accounts.authority.key;
+        autocallable.underlying_symbol = underlying_symbol;
+        autocallable.initial_principal = initial_principal;
+        autocallable.initial_fixing_price = initial_fixing_price;
+        // initial fixing prices scaled by the basis points
+        autocallable.autocall_trigger =
+            initial_fixing_price * autocall_trigger_in_basis_points / 10000;
+        autocallable.barrier = initial_fixing_price * barrier_in_basis_points / 10000;
+        autocallable.coupon_per_unit = coupon_per_unit;
+        autocallable.dummy_oracle = ctx.accounts.dummy_oracle.key();
+        autocallable.observation_date_offsets = observation_date_offsets;
+        autocallable.max_staleness = max_staleness;
+        autocallable.fixing_window = fixing_window;
+        autocallable.next_observation = 0;
+        // to be set once the product redeems
+        autocallable.redemption_date_offset = None;
+        autocallable.redemption_per_unit = None;
+
+        autocallable.bump = ctx.bumps.autocallable;
+        msg!("Autocallable: {:?}", autocallable);
+
+        Ok(())
+    }
+
+    // Observations have to be done in order. Each observation sets the price of the payment
+    // at the observation date, the last observation sets the principal payment.
+    pub fn observe(ctx: Context<Observe>, payment_date_offset: i64) -> Result<()> {
+        let autocallable = &ctx.accounts.autocallable;
+        let observation_index = autocallable.next_observation as usize;
+
+        require!(
+            observation_index < autocallable.observation_date_offsets.len(),
+            AutocallPriceAuthorityError::ObservationsCompleted
+        );
+        require!(
+            autocallable.observation_date_offsets[observation_index] == payment_date_offset,
+            AutocallPriceAuthorityError::InvalidObservationDate
+        );
+
+        let final_observation =
+            observation_index == autocallable.observation_date_offsets.len() - 1;
+
+        require!(
+            ctx.accounts.payment.principal == final_observation,
+            AutocallPriceAuthorityError::InvalidObservationDate
+        );
+
+        // Once redeemed, all remaining payments are zero and no fixing is needed
+        let payout = match autocallable.redemption_per_unit {
+            Some(_) => None,
+            None => {
+                // observation dates are offsets to the issuance date
+                let observation_date = ctx
+                    .accounts
+                    .structured_product
+                    .issuance_date()
+                    .ok_or(AutocallPriceAuthorityError::NotIssued)?
+                    + payment_date_offset;
+
+                validate_observation_timestamp(
+                    ctx.accounts.dummy_oracle.last_update,
+                    observation_date,
+                    autocallable.fixing_window,
+                    autocallable.max_staleness,
+                    Clock::get()?.unix_timestamp,
+                )?;
+
+                let fixing_price = ctx.accounts.dummy_oracle.current_price;
+                msg!(
+                    "Fixing price: {}, autocall_trigger: {}",
+                    fixing_price,
+                    autocallable.autocall_trigger
+                );
+
+                calc_observation_payout(
+                    autocallable.initial_principal,
+                    autocallable.initial_fixing_price,
+                    autocallable.autocall_trigger,
+                    autocallable.barrier,
+                    autocallable.coupon_per_unit,
+                    fixing_price,
+                    final_observation,
+                )
+            }
+        };
+
+        msg!(
+            "Observation {} at {}, payout: {:?}",
+            observation_index,
+            payment_date_offset,
+            payout
+        );
+
+        let cpi_program = ctx.accounts.structured_product_program.to_account_info();
+
+        let cpi_accounts = SetPaymentPrice {
+            authority: ctx.accounts.autocallable.to_account_info(),
+            payment: ctx.accounts.payment.to_account_info(),
+            structured_product: ctx.accounts.structured_product.to_account_info(),
+        };
+
+        let structured_product_key = ctx.accounts.structured_product.key();
+
+        let seeds = &[
+            structured_product_key.as_ref(),
+            &[ctx.accounts.autocallable.bump],
+        ];
+
+        set_payment_price(
+            CpiContext::new_with_signer(cpi_program, cpi_accounts, &[&seeds[..]]),
+            payment_date_offset,
+            payout.unwrap_or(0),
+        )?;
+
+        let autocallable = &mut ctx.accounts.autocallable;
+        autocallable.next_observation += 1;
+
+        if payout.is_some() {
+            autocallable.redemption_date_offset = Some(payment_date_offset);
+            autocallable.redemption_per_unit = payout;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(underlying_symbol: String, observation_date_offsets: Vec<i64>)]
+pub struct Initialize<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account()]
+    pub structured_product: Account<'info, StructuredProductConfig>,
+    #[account(init,
+    seeds=[structured_product.key().as_ref()],
+    bump,
+    payer=authority,
+    space=Autocallable::space(underlying_symbol.len(), observation_date_offsets.len()))]
+    pub autocallable: Account<'info, Autocallable>,
+    #[account(seeds=[authority.key().as_ref(), underlying_symbol.as_bytes()], bump=dummy_oracle.bump, seeds::program=dummy_oracle_program)]
+    pub dummy_oracle: Account<'info, DummyOracleAccount>,
+    pub dummy_oracle_program: Program<'info, DummyOracle>,
+    pub structured_product_program: Program<'info, StructuredProduct>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(payment_date_offset: i64)]
+pub struct Observe<'info> {
+    pub payer: Signer<'info>,
+    #[account(mut, seeds=[structured_product.key().as_ref()], bump=autocallable.bump)]
+    pub autocallable: Account<'info, Autocallable>,
+    pub structured_product: Account<'info, StructuredProductConfig>,
+    #[account(mut,
+    seeds=[structured_product.key().as_ref(), &[payment.principal.into()], &payment_date_offset.to_le_bytes()],
+    bump=payment.bump,
+    seeds::program=structured_product_program)]
+    pub payment: Account<'info, Payment>,
+    #[account(address=autocallable.dummy_oracle @ AutocallPriceAuthorityError::Unauthorized)]
+    pub dummy_oracle: Account<'info, DummyOracleAccount>,
+    pub structured_product_program: Program<'info, StructuredProduct>,
+}
+
+#[account]
+#[derive(Debug)]
+pub struct Autocallable {
+    pub authority: Pubkey,
+    pub underlying_symbol: String,
+    pub initial_principal: u64,
+    pub initial_fixing_price: u64,
+    pub autocall_trigger: u64,
+    pub barrier: u64,
+    pub coupon_per_unit: u64,
+    pub dummy_oracle: Pubkey,
+    pub observation_date_offsets: Vec<i64>,
+    pub max_staleness: i64,
+    pub fixing_window: i64,
+    pub next_observation: u8,
+    pub redemption_date_offset: Option<i64>,
+    pub redemption_per_unit: Option<u64>,
+    pub bump: u8,
+}
+
+impl Autocallable {
+    pub fn space(underlying_symbol_len_in_bytes: usize, num_observations: usize) -> usize {
+        8 + 32
+            + 4
+            + underlying_symbol_len_in_bytes
+            + 8
+            + 8
+            + 8
+            + 8
+            + 8
+            + 32
+            + 4
+            + 8 * num_observations
+            + 8
+            + 8
+            + 1
+            + 9
+            + 9
+            + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! calc_observation_payout_tests {
+        ($($name:ident: $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (initial_principal, initial_fixing_price, autocall_trigger, barrier, coupon_per_unit, fixing_price, final_observation, expected) = $expected;
+                    let result = calc_observation_payout(
+                        initial_principal,
+                        initial_fixing_price,
+                        autocall_trigger,
+                        barrier,
+                        coupon_per_unit,
+                        fixing_price,
+                        final_observation,
+                    );
+                    assert_eq!(result, expected);
+                }
+            )*
+        }
+    }
+
+    #[cfg(test)]
+    calc_observation_payout_tests! {
+        observation_payout_test_1: (100000, 42000, 42000, 29400, 5000, 55000, false, Some(105000),),
+        observation_payout_test_2: (100000, 42000, 42000, 29400, 5000, 42000, false, Some(105000),),
+        observation_payout_test_3: (100000, 42000, 42000, 29400, 5000, 41999, false, None,),
+        observation_payout_test_4: (100000, 42000, 42000, 29400, 5000, 0, false, None,),
+        observation_payout_test_5: (100000, 42000, 42000, 29400, 5000, 42000, true, Some(105000),),
+        observation_payout_test_6: (100000, 42000, 42000, 29400, 5000, 41999, true, Some(100000),),
+        observation_payout_test_7: (100000, 42000, 42000, 29400, 5000, 29401, true, Some(100000),),
+        observation_payout_test_8: (100000, 42000, 42000, 29400, 5000, 29400, true, Some(70000),),
+        observation_payout_test_9: (100000000000, 42000000000, 42000000000, 29400000000, 0, 21000000000, true, Some(50000000000),),
+        observation_payout_test_10: (100000, 42000, 42000, 29400, 5000, 0, true, Some(0),),
+        observation_payout_test_11: (100000, 42000, 37800, 29400, 5000, 37800, false, Some(105000),),
+        observation_payout_test_12: (100000, 42000, 37800, 29400, 5000, 37799, false, None,),
+    }
+}
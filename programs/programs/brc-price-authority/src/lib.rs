@@ -1,11 +1,89 @@
+// Instructions and their generated cpi helpers take many arguments
+#![allow(clippy::too_many_arguments)]
+
 use anchor_lang::prelude::*;
+use anchor_spl::token_interface::Mint;
 use dummy_oracle::program::DummyOracle;
-use dummy_oracle::DummyOracleAccount;
 use structured_product::program::StructuredProduct;
 use structured_product::{Payment, StructuredProductConfig};
 
+pub use oracle::*;
+
+pub mod oracle;
+
 declare_id!("brcsirKCcw5WUZQatGvwwZ2w2ikZegCXooGHpaQZWGm");
 
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    Down,
+    Up,
+}
+
+// a * b / c, None on division by zero or if the result does not fit into u64
+pub fn mul_div(a: u64, b: u64, c: u64, rounding: Rounding) -> Option<u64> {
+    if c == 0 {
+        return None;
+    }
+    let product = a as u128 * b as u128;
+    let result = match rounding {
+        Rounding::Down => product / c as u128,
+        Rounding::Up => product.div_ceil(c as u128),
+    };
+    u64::try_from(result).ok()
+}
+
+// Converts an amount of 10^from_exponent units into 10^to_exponent units,
+// None if the result does not fit into u64
+pub fn rescale(
+    amount: u64,
+    from_exponent: i32,
+    to_exponent: i32,
+    rounding: Rounding,
+) -> Option<u64> {
+    let exponent_diff = to_exponent as i64 - from_exponent as i64;
+    match exponent_diff {
+        0 => Some(amount),
+        // less precision, divide
+        exponent_diff if exponent_diff > 0 => match 10u128.checked_pow(exponent_diff as u32) {
+            Some(divisor) => {
+                let result = match rounding {
+                    Rounding::Down => amount as u128 / divisor,
+                    Rounding::Up => (amount as u128).div_ceil(divisor),
+                };
+                u64::try_from(result).ok()
+            }
+            // the divisor exceeds any u64 amount
+            None => match rounding {
+                Rounding::Down => Some(0),
+                Rounding::Up => Some((amount > 0).into()),
+            },
+        },
+        // more precision, multiply
+        exponent_diff => match amount {
+            0 => Some(0),
+            _ => 10u64
+                .checked_pow(exponent_diff.unsigned_abs().try_into().ok()?)
+                .and_then(|multiplier| amount.checked_mul(multiplier)),
+        },
+    }
+}
+
+// Converts an amount in quote currency base units into payment mint base units,
+// both are assumed to be the same currency
+pub fn convert_quote_to_payment(
+    amount: u64,
+    quote_decimals: u8,
+    payment_decimals: u8,
+    rounding: Rounding,
+) -> Option<u64> {
+    rescale(
+        amount,
+        -(quote_decimals as i32),
+        -(payment_decimals as i32),
+        rounding,
+    )
+}
+
 pub fn calc_final_principal(
     initial_principal: u64,
     initial_fixing_price: u64,
@@ -21,27 +99,248 @@ pub fn calc_final_principal(
     }
 }
 
+// Once knocked in, the barrier no longer matters: the principal follows the underlying
+// whenever the final fixing price is below the strike (initial fixing price).
+pub fn calc_knocked_in_final_principal(
+    initial_principal: u64,
+    initial_fixing_price: u64,
+    final_underlying_fixing_price: u64,
+) -> u64 {
+    match final_underlying_fixing_price {
+        final_fixing_price if final_fixing_price < initial_fixing_price => {
+            ((initial_principal as u128 * final_fixing_price as u128)
+                / initial_fixing_price as u128) as u64
+        }
+        _ => initial_principal,
+    }
+}
+
+// The worst performing underlying is the one with the lowest final to initial fixing price ratio.
+// If any underlying closes at or below its barrier, the principal follows the worst performance.
+// Underlyings are given as (initial_fixing_price, barrier, final_fixing_price).
+pub fn calc_worst_of_final_principal(
+    initial_principal: u64,
+    underlyings: &[(u64, u64, u64)],
+) -> u64 {
+    let knocked_in = underlyings
+        .iter()
+        .any(|&(_, barrier, final_fixing_price)| final_fixing_price <= barrier);
+
+    if !knocked_in {
+        return initial_principal;
+    }
+
+    let worst = underlyings
+        .iter()
+        .min_by(|a, b| (a.2 as u128 * b.0 as u128).cmp(&(b.2 as u128 * a.0 as u128)));
+
+    match worst {
+        Some(&(initial_fixing_price, _, final_fixing_price))
+            if final_fixing_price < initial_fixing_price =>
+        {
+            ((initial_principal as u128 * final_fixing_price as u128)
+                / initial_fixing_price as u128) as u64
+        }
+        _ => initial_principal,
+    }
+}
+
+// The principal is protected at the protection level and participates in the performance
+// above the strike, the redemption is capped. Below the strike the principal follows the
+// underlying down to the protection level. Levels are given in basis points of the principal.
+pub fn calc_capital_protected_final_principal(
+    initial_principal: u64,
+    strike: u64,
+    protection_in_basis_points: u64,
+    participation_in_basis_points: u64,
+    cap_in_basis_points: u64,
+    final_underlying_fixing_price: u64,
+) -> u64 {
+    let principal = initial_principal as u128;
+    let strike = strike as u128;
+    let final_fixing_price = final_underlying_fixing_price as u128;
+
+    let protected = principal * protection_in_basis_points as u128 / 10000;
+    let cap = principal * cap_in_basis_points as u128 / 10000;
+
+    let redemption = match final_fixing_price {
+        final_fixing_price if final_fixing_price > strike => {
+            principal
+                + principal * (final_fixing_price - strike) * participation_in_basis_points as u128
+                    / (strike * 10000)
+        }
+        final_fixing_price => principal * final_fixing_price / strike,
+    };
+
+    redemption.min(cap).max(protected).min(u64::MAX as u128) as u64
+}
+
+// Physical settlement delivers initial principal / strike underlying tokens per unit. Only whole
+// tokens are delivered, the fraction is paid in cash at the final fixing price, rounded down.
+// Returns the whole tokens and the cash remainder, principal and prices have to be in the same units.
+pub fn calc_physical_delivery(
+    initial_principal: u64,
+    initial_fixing_price: u64,
+    final_underlying_fixing_price: u64,
+) -> Option<(u64, u64)> {
+    if initial_fixing_price == 0 {
+        return None;
+    }
+    let tokens = initial_principal / initial_fixing_price;
+    let cash_remainder = mul_div(
+        initial_principal % initial_fixing_price,
+        final_underlying_fixing_price,
+        initial_fixing_price,
+        Rounding::Down,
+    )?;
+    Some((tokens, cash_remainder))
+}
+
+// A zero max_staleness would reject every oracle price, a negative fixing window every fixing date.
+pub fn validate_fixing_settings(max_staleness: i64, fixing_window: i64) -> Result<()> {
+    require!(
+        max_staleness > 0 && fixing_window >= 0,
+        BRCPriceAuthorityError::InvalidFixingSettings
+    );
+    Ok(())
+}
+
+// The oracle price must have been updated within the fixing window around the fixing date
+// and must not be older than max_staleness at the time of fixing.
+pub fn validate_fixing_timestamp(
+    last_update: i64,
+    fixing_date: i64,
+    fixing_window: i64,
+    max_staleness: i64,
+    current_time: i64,
+) -> Result<()> {
+    require!(
+        last_update >= fixing_date - fixing_window && last_update <= fixing_date + fixing_window,
+        BRCPriceAuthorityError::FixingOutsideWindow
+    );
+    require!(
+        current_time - last_update <= max_staleness,
+        BRCPriceAuthorityError::StalePrice
+    );
+    Ok(())
+}
+
+// Observations can only be made once the observation date has passed,
+// on an oracle price published at or after the observation date.
+pub fn validate_observation_timestamp(
+    last_update: i64,
+    observation_date: i64,
+    fixing_window: i64,
+    max_staleness: i64,
+    current_time: i64,
+) -> Result<()> {
+    require!(
+        observation_date <= current_time,
+        BRCPriceAuthorityError::ObservationNotDue
+    );
+    require!(
+        last_update >= observation_date,
+        BRCPriceAuthorityError::PriceBeforeObservationDate
+    );
+    validate_fixing_timestamp(
+        last_update,
+        observation_date,
+        fixing_window,
+        max_staleness,
+        current_time,
+    )
+}
+
+// Barrier observations need a fresh price published during the life of the product
+pub fn validate_barrier_observation_timestamp(
+    last_update: i64,
+    issuance_date: i64,
+    max_staleness: i64,
+    current_time: i64,
+) -> Result<()> {
+    require!(
+        last_update >= issuance_date,
+        BRCPriceAuthorityError::PriceBeforeIssuance
+    );
+    require!(
+        current_time - last_update <= max_staleness,
+        BRCPriceAuthorityError::StalePrice
+    );
+    Ok(())
+}
+
 #[error_code]
 pub enum BRCPriceAuthorityError {
     #[msg("Unauthorized")]
     Unauthorized,
+    #[msg("Invalid underlyings")]
+    InvalidUnderlyings,
+    #[msg("Invalid oracle")]
+    InvalidOracle,
+    #[msg("Barrier is not continuously observed")]
+    ContinuousBarrierDisabled,
+    #[msg("Final fixing price already set")]
+    AlreadyFixed,
+    #[msg("Oracle price outside of fixing window")]
+    FixingOutsideWindow,
+    #[msg("Oracle price is stale")]
+    StalePrice,
+    #[msg("Product not issued")]
+    NotIssued,
+    #[msg("Invalid oracle price")]
+    InvalidOraclePrice,
+    #[msg("Invalid payment mint")]
+    InvalidPaymentMint,
+    #[msg("Amount overflow")]
+    Overflow,
+    #[msg("Invalid payoff levels")]
+    InvalidPayoffLevels,
+    #[msg("Invalid delivery mint")]
+    InvalidDeliveryMint,
+    #[msg("Oracle price published before issuance")]
+    PriceBeforeIssuance,
+    #[msg("Observation date not reached")]
+    ObservationNotDue,
+    #[msg("Oracle price published before the observation date")]
+    PriceBeforeObservationDate,
+    #[msg("Invalid max staleness or fixing window")]
+    InvalidFixingSettings,
 }
 
+pub const MIN_WORST_OF_UNDERLYINGS: usize = 2;
+pub const MAX_WORST_OF_UNDERLYINGS: usize = 5;
+
 #[program]
 pub mod brc_price_authority {
     use super::*;
     use structured_product::cpi::accounts::SetPaymentPrice;
-    use structured_product::cpi::set_payment_price;
+    use structured_product::cpi::{set_payment_delivery, set_payment_price};
 
+    // The initial fixing price is given in 10^price_exponent units of the quote currency
+    // and the initial principal in quote currency mint base units.
     pub fn initialize(
         ctx: Context<Initialize>,
         underlying_symbol: String,
         _payment_date_offset: i64,
         initial_principal: u64,
         initial_fixing_price: u64,
+        price_exponent: i32,
         barrier_in_basis_points: u64,
+        continuous_barrier: bool,
+        max_staleness: i64,
+        fixing_window: i64,
+        oracle_source: OracleSource,
     ) -> Result<()> {
+        validate_fixing_settings(max_staleness, fixing_window)?;
+
         msg!("Initializing BRC at {}", ctx.accounts.brc.key().clone());
+
+        oracle_source.validate_oracle(
+            &ctx.accounts.oracle,
+            ctx.accounts.authority.key,
+            &underlying_symbol,
+        )?;
+
         let brc = &mut ctx.accounts.brc;
 
         brc.authority = *ctx.accounts.authority.key;
@@ -50,12 +349,31 @@ pub mod brc_price_authority {
         brc.initial_fixing_price = initial_fixing_price;
         // initial fixing prices scaled by the barrier_in_basis_points
         brc.barrier = initial_fixing_price * barrier_in_basis_points / 10000;
+        brc.price_exponent = price_exponent;
+        brc.quote_decimals = ctx.accounts.quote_currency_mint.decimals;
+        brc.payment_mint = ctx.accounts.payment_mint.key();
+        brc.payment_decimals = ctx.accounts.payment_mint.decimals;
+        // knock-ins are physically settled in the delivery mint if given
+        brc.delivery_mint = ctx.accounts.delivery_mint.as_ref().map(|mint| mint.key());
+        brc.delivery_decimals = ctx
+            .accounts
+            .delivery_mint
+            .as_ref()
+            .map_or(0, |mint| mint.decimals);
         brc.target_payment = ctx.accounts.payment.key();
-        brc.dummy_oracle = ctx.accounts.dummy_oracle.key();
+        brc.oracle = ctx.accounts.oracle.key();
+        brc.oracle_source = oracle_source;
+        brc.continuous_barrier = continuous_barrier;
+        brc.max_staleness = max_staleness;
+        brc.fixing_window = fixing_window;
+        // to be latched by observe_barrier
+        brc.knocked_in = false;
+        brc.knock_in_date = None;
         // to be set by the final fixing price
         brc.final_principal = None;
         brc.final_underlying_fixing_price = None;
         brc.final_fixing_date = None;
+        brc.final_delivery_per_unit = None;
 
         brc.bump = ctx.bumps.brc;
         msg!("Brc: {:?}", brc);
@@ -72,21 +390,101 @@ pub mod brc_price_authority {
             ctx.accounts.payment.key() == ctx.accounts.brc.target_payment,
             BRCPriceAuthorityError::Unauthorized
         );
+        require!(
+            ctx.accounts.payment.payment_mint == ctx.accounts.brc.payment_mint,
+            BRCPriceAuthorityError::InvalidPaymentMint
+        );
         let brc = &ctx.accounts.brc;
 
         let initial_principal = brc.initial_principal;
         let initial_fixing_price = brc.initial_fixing_price;
         let barrier = brc.barrier;
-        let final_fixing_price = ctx.accounts.dummy_oracle.current_price;
+        let oracle_price = brc.oracle_source.read_price(&ctx.accounts.oracle)?;
+        // fixing prices are compared in the scale of the initial fixing price
+        let final_fixing_price = rescale(
+            oracle_price.price as u64,
+            oracle_price.exponent,
+            brc.price_exponent,
+            Rounding::Down,
+        )
+        .ok_or(BRCPriceAuthorityError::Overflow)?;
 
-        let final_principal = calc_final_principal(
-            initial_principal,
-            initial_fixing_price,
-            barrier,
-            final_fixing_price,
+        // payment dates are offsets to the issuance date
+        let fixing_date = ctx
+            .accounts
+            .structured_product
+            .issuance_date()
+            .ok_or(BRCPriceAuthorityError::NotIssued)?
+            + payment_date_offset;
+
+        msg!(
+            "Fixing date: {}, oracle publish time: {}",
+            fixing_date,
+            oracle_price.publish_time
         );
 
+        validate_fixing_timestamp(
+            oracle_price.publish_time,
+            fixing_date,
+            brc.fixing_window,
+            brc.max_staleness,
+            Clock::get()?.unix_timestamp,
+        )?;
+
+        let final_principal = match brc.knocked_in {
+            true => calc_knocked_in_final_principal(
+                initial_principal,
+                initial_fixing_price,
+                final_fixing_price,
+            ),
+            false => calc_final_principal(
+                initial_principal,
+                initial_fixing_price,
+                barrier,
+                final_fixing_price,
+            ),
+        };
+
+        // below par physically settled products deliver the underlying, the cash leg is the remainder
+        let delivery = match brc.delivery_mint {
+            Some(delivery_mint) if final_principal < initial_principal => {
+                require!(
+                    ctx.accounts.payment.delivery_mint == Some(delivery_mint),
+                    BRCPriceAuthorityError::InvalidDeliveryMint
+                );
+                let to_quote_units = |price: u64| {
+                    rescale(
+                        price,
+                        brc.price_exponent,
+                        -(brc.quote_decimals as i32),
+                        Rounding::Down,
+                    )
+                };
+                let (tokens, cash_remainder) = calc_physical_delivery(
+                    initial_principal,
+                    to_quote_units(initial_fixing_price).ok_or(BRCPriceAuthorityError::Overflow)?,
+                    to_quote_units(final_fixing_price).ok_or(BRCPriceAuthorityError::Overflow)?,
+                )
+                .ok_or(BRCPriceAuthorityError::InvalidPayoffLevels)?;
+                let delivery_per_unit =
+                    rescale(tokens, 0, -(brc.delivery_decimals as i32), Rounding::Down)
+                        .ok_or(BRCPriceAuthorityError::Overflow)?;
+                Some((delivery_per_unit, cash_remainder))
+            }
+            _ => None,
+        };
+
+        // investor payouts are rounded down
+        let final_principal = convert_quote_to_payment(
+            delivery.map_or(final_principal, |(_, cash_remainder)| cash_remainder),
+            brc.quote_decimals,
+            brc.payment_decimals,
+            Rounding::Down,
+        )
+        .ok_or(BRCPriceAuthorityError::Overflow)?;
+
         msg!("Finalizing brc at {}", ctx.accounts.brc.key());
+        msg!("Knocked in: {}", brc.knocked_in);
         msg!("Initial principal: {}, initial_fixing_price: {}, barrier: {}, final_fixing_price: {}, Final principal: {}",
             initial_principal, initial_fixing_price, barrier, final_fixing_price, final_principal);
 
@@ -102,17 +500,449 @@ pub mod brc_price_authority {
 
         let seeds = &[structured_product_key.as_ref(), &[ctx.accounts.brc.bump]];
 
+        match delivery {
+            Some((delivery_per_unit, _)) => {
+                msg!("Physical delivery per unit: {}", delivery_per_unit);
+                set_payment_delivery(
+                    CpiContext::new_with_signer(cpi_program, cpi_accounts, &[&seeds[..]]),
+                    payment_date_offset,
+                    delivery_per_unit,
+                    final_principal,
+                )?;
+            }
+            None => {
+                set_payment_price(
+                    CpiContext::new_with_signer(cpi_program, cpi_accounts, &[&seeds[..]]),
+                    payment_date_offset,
+                    final_principal,
+                )?;
+            }
+        }
+
+        let brc = &mut ctx.accounts.brc;
+
+        brc.final_underlying_fixing_price = Some(final_fixing_price);
+        brc.final_principal = Some(final_principal);
+        brc.final_delivery_per_unit = delivery.map(|(delivery_per_unit, _)| delivery_per_unit);
+        brc.final_fixing_date = Some(Clock::get()?.unix_timestamp);
+        Ok(())
+    }
+
+    // Permissionless, anybody can report the barrier being touched during the life of the product
+    pub fn observe_barrier(ctx: Context<ObserveBarrier>) -> Result<()> {
+        let brc = &ctx.accounts.brc;
+        require!(
+            brc.continuous_barrier,
+            BRCPriceAuthorityError::ContinuousBarrierDisabled
+        );
+        require!(
+            brc.final_principal.is_none(),
+            BRCPriceAuthorityError::AlreadyFixed
+        );
+
+        let issuance_date = ctx
+            .accounts
+            .structured_product
+            .issuance_date()
+            .ok_or(BRCPriceAuthorityError::NotIssued)?;
+
+        let oracle_price = brc.oracle_source.read_price(&ctx.accounts.oracle)?;
+        validate_barrier_observation_timestamp(
+            oracle_price.publish_time,
+            issuance_date,
+            brc.max_staleness,
+            Clock::get()?.unix_timestamp,
+        )?;
+
+        let current_price = rescale(
+            oracle_price.price as u64,
+            oracle_price.exponent,
+            brc.price_exponent,
+            Rounding::Down,
+        )
+        .ok_or(BRCPriceAuthorityError::Overflow)?;
+
+        msg!(
+            "Observing barrier: {}, current_price: {}",
+            brc.barrier,
+            current_price
+        );
+
+        if !brc.knocked_in && current_price <= brc.barrier {
+            let brc = &mut ctx.accounts.brc;
+            brc.knocked_in = true;
+            brc.knock_in_date = Some(Clock::get()?.unix_timestamp);
+            msg!("Knocked in at {:?}", brc.knock_in_date);
+        }
+
+        Ok(())
+    }
+
+    // The oracle of every underlying has to be passed as remaining accounts, in order.
+    // Initial fixing prices are given in 10^price_exponent units of the quote currency
+    // and the initial principal in quote currency mint base units.
+    pub fn initialize_worst_of<'info>(
+        ctx: Context<'_, '_, 'info, 'info, InitializeWorstOf<'info>>,
+        underlying_symbols: Vec<String>,
+        _payment_date_offset: i64,
+        initial_principal: u64,
+        initial_fixing_prices: Vec<u64>,
+        barriers_in_basis_points: Vec<u64>,
+        price_exponent: i32,
+        max_staleness: i64,
+        fixing_window: i64,
+        oracle_source: OracleSource,
+    ) -> Result<()> {
+        validate_fixing_settings(max_staleness, fixing_window)?;
+
+        let num_underlyings = underlying_symbols.len();
+        require!(
+            (MIN_WORST_OF_UNDERLYINGS..=MAX_WORST_OF_UNDERLYINGS).contains(&num_underlyings)
+                && initial_fixing_prices.len() == num_underlyings
+                && barriers_in_basis_points.len() == num_underlyings
+                && ctx.remaining_accounts.len() == num_underlyings,
+            BRCPriceAuthorityError::InvalidUnderlyings
+        );
+
+        msg!(
+            "Initializing worst of BRC at {}",
+            ctx.accounts.worst_of_brc.key().clone()
+        );
+
+        let mut underlyings = Vec::with_capacity(num_underlyings);
+
+        for (i, underlying_symbol) in underlying_symbols.into_iter().enumerate() {
+            let oracle_info = &ctx.remaining_accounts[i];
+            oracle_source.validate_oracle(
+                oracle_info,
+                ctx.accounts.authority.key,
+                &underlying_symbol,
+            )?;
+
+            underlyings.push(WorstOfUnderlying {
+                underlying_symbol,
+                oracle: oracle_info.key(),
+                initial_fixing_price: initial_fixing_prices[i],
+                // initial fixing prices scaled by the barrier_in_basis_points
+                barrier: initial_fixing_prices[i] * barriers_in_basis_points[i] / 10000,
+                final_fixing_price: None,
+            });
+        }
+
+        let worst_of_brc = &mut ctx.accounts.worst_of_brc;
+
+        worst_of_brc.authority = *ctx.accounts.authority.key;
+        worst_of_brc.initial_principal = initial_principal;
+        worst_of_brc.underlyings = underlyings;
+        worst_of_brc.price_exponent = price_exponent;
+        worst_of_brc.quote_decimals = ctx.accounts.quote_currency_mint.decimals;
+        worst_of_brc.payment_mint = ctx.accounts.payment_mint.key();
+        worst_of_brc.payment_decimals = ctx.accounts.payment_mint.decimals;
+        worst_of_brc.oracle_source = oracle_source;
+        worst_of_brc.max_staleness = max_staleness;
+        worst_of_brc.fixing_window = fixing_window;
+        worst_of_brc.target_payment = ctx.accounts.payment.key();
+        // to be set by the final fixing price
+        worst_of_brc.final_principal = None;
+        worst_of_brc.final_fixing_date = None;
+
+        worst_of_brc.bump = ctx.bumps.worst_of_brc;
+        msg!("Worst of BRC: {:?}", worst_of_brc);
+
+        Ok(())
+    }
+
+    // The oracle of every underlying has to be passed as remaining accounts, in order.
+    pub fn set_worst_of_final_fixing_price<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SetWorstOfFinalFixingPrice<'info>>,
+        payment_date_offset: i64,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.payment.key() == ctx.accounts.worst_of_brc.target_payment,
+            BRCPriceAuthorityError::Unauthorized
+        );
+        require!(
+            ctx.accounts.payment.payment_mint == ctx.accounts.worst_of_brc.payment_mint,
+            BRCPriceAuthorityError::InvalidPaymentMint
+        );
+
+        // payment dates are offsets to the issuance date
+        let fixing_date = ctx
+            .accounts
+            .structured_product
+            .issuance_date()
+            .ok_or(BRCPriceAuthorityError::NotIssued)?
+            + payment_date_offset;
+        let current_time = Clock::get()?.unix_timestamp;
+
+        let worst_of_brc = &mut ctx.accounts.worst_of_brc;
+
+        require!(
+            ctx.remaining_accounts.len() == worst_of_brc.underlyings.len(),
+            BRCPriceAuthorityError::InvalidUnderlyings
+        );
+
+        let oracle_source = worst_of_brc.oracle_source;
+        let price_exponent = worst_of_brc.price_exponent;
+        let fixing_window = worst_of_brc.fixing_window;
+        let max_staleness = worst_of_brc.max_staleness;
+
+        for (underlying, oracle_info) in worst_of_brc
+            .underlyings
+            .iter_mut()
+            .zip(ctx.remaining_accounts)
+        {
+            require!(
+                oracle_info.key() == underlying.oracle,
+                BRCPriceAuthorityError::InvalidOracle
+            );
+            let oracle_price = oracle_source.read_price(oracle_info)?;
+            validate_fixing_timestamp(
+                oracle_price.publish_time,
+                fixing_date,
+                fixing_window,
+                max_staleness,
+                current_time,
+            )?;
+            // fixing prices are compared in the scale of the initial fixing prices
+            let final_fixing_price = rescale(
+                oracle_price.price as u64,
+                oracle_price.exponent,
+                price_exponent,
+                Rounding::Down,
+            )
+            .ok_or(BRCPriceAuthorityError::Overflow)?;
+            underlying.final_fixing_price = Some(final_fixing_price);
+
+            msg!(
+                "{}: initial_fixing_price: {}, barrier: {}, final_fixing_price: {}",
+                underlying.underlying_symbol,
+                underlying.initial_fixing_price,
+                underlying.barrier,
+                final_fixing_price
+            );
+        }
+
+        let fixings: Vec<(u64, u64, u64)> = worst_of_brc
+            .underlyings
+            .iter()
+            .map(|underlying| {
+                (
+                    underlying.initial_fixing_price,
+                    underlying.barrier,
+                    underlying.final_fixing_price.unwrap(),
+                )
+            })
+            .collect();
+
+        let final_principal =
+            calc_worst_of_final_principal(worst_of_brc.initial_principal, &fixings);
+
+        // investor payouts are rounded down
+        let final_principal = convert_quote_to_payment(
+            final_principal,
+            worst_of_brc.quote_decimals,
+            worst_of_brc.payment_decimals,
+            Rounding::Down,
+        )
+        .ok_or(BRCPriceAuthorityError::Overflow)?;
+
+        msg!("Finalizing worst of brc at {}", worst_of_brc.key());
+        msg!(
+            "Initial principal: {}, Final principal: {}",
+            worst_of_brc.initial_principal,
+            final_principal
+        );
+
+        let cpi_program = ctx.accounts.structured_product_program.to_account_info();
+
+        let cpi_accounts = SetPaymentPrice {
+            authority: ctx.accounts.worst_of_brc.to_account_info(),
+            payment: ctx.accounts.payment.to_account_info(),
+            structured_product: ctx.accounts.structured_product.to_account_info(),
+        };
+
+        let structured_product_key = ctx.accounts.structured_product.key();
+
+        let seeds = &[
+            b"worst-of".as_ref(),
+            structured_product_key.as_ref(),
+            &[ctx.accounts.worst_of_brc.bump],
+        ];
+
         set_payment_price(
             CpiContext::new_with_signer(cpi_program, cpi_accounts, &[&seeds[..]]),
             payment_date_offset,
             final_principal,
         )?;
 
-        let brc = &mut ctx.accounts.brc;
+        let worst_of_brc = &mut ctx.accounts.worst_of_brc;
+
+        worst_of_brc.final_principal = Some(final_principal);
+        worst_of_brc.final_fixing_date = Some(Clock::get()?.unix_timestamp);
+        Ok(())
+    }
+
+    // The strike is given in 10^price_exponent units of the quote currency
+    // and the initial principal in quote currency mint base units.
+    pub fn initialize_capital_protected(
+        ctx: Context<InitializeCapitalProtected>,
+        underlying_symbol: String,
+        _payment_date_offset: i64,
+        initial_principal: u64,
+        strike: u64,
+        price_exponent: i32,
+        protection_in_basis_points: u64,
+        participation_in_basis_points: u64,
+        cap_in_basis_points: u64,
+        max_staleness: i64,
+        fixing_window: i64,
+        oracle_source: OracleSource,
+    ) -> Result<()> {
+        validate_fixing_settings(max_staleness, fixing_window)?;
+        require!(
+            strike > 0 && cap_in_basis_points >= protection_in_basis_points,
+            BRCPriceAuthorityError::InvalidPayoffLevels
+        );
+
+        msg!(
+            "Initializing capital protected note at {}",
+            ctx.accounts.capital_protected_note.key()
+        );
+
+        oracle_source.validate_oracle(
+            &ctx.accounts.oracle,
+            ctx.accounts.authority.key,
+            &underlying_symbol,
+        )?;
+
+        let note = &mut ctx.accounts.capital_protected_note;
+
+        note.authority = *ctx.accounts.authority.key;
+        note.underlying_symbol = underlying_symbol;
+        note.initial_principal = initial_principal;
+        note.strike = strike;
+        note.price_exponent = price_exponent;
+        note.protection_in_basis_points = protection_in_basis_points;
+        note.participation_in_basis_points = participation_in_basis_points;
+        note.cap_in_basis_points = cap_in_basis_points;
+        note.quote_decimals = ctx.accounts.quote_currency_mint.decimals;
+        note.payment_mint = ctx.accounts.payment_mint.key();
+        note.payment_decimals = ctx.accounts.payment_mint.decimals;
+        note.target_payment = ctx.accounts.payment.key();
+        note.oracle = ctx.accounts.oracle.key();
+        note.oracle_source = oracle_source;
+        note.max_staleness = max_staleness;
+        note.fixing_window = fixing_window;
+        // to be set by the final fixing price
+        note.final_principal = None;
+        note.final_underlying_fixing_price = None;
+        note.final_fixing_date = None;
+
+        note.bump = ctx.bumps.capital_protected_note;
+        msg!("Capital protected note: {:?}", note);
 
-        brc.final_underlying_fixing_price = Some(final_fixing_price);
-        brc.final_principal = Some(final_principal);
-        brc.final_fixing_date = Some(Clock::get()?.unix_timestamp);
+        Ok(())
+    }
+
+    pub fn set_capital_protected_final_fixing_price(
+        ctx: Context<SetCapitalProtectedFinalFixingPrice>,
+        payment_date_offset: i64,
+    ) -> Result<()> {
+        let note = &ctx.accounts.capital_protected_note;
+        require!(
+            ctx.accounts.payment.key() == note.target_payment,
+            BRCPriceAuthorityError::Unauthorized
+        );
+        require!(
+            ctx.accounts.payment.payment_mint == note.payment_mint,
+            BRCPriceAuthorityError::InvalidPaymentMint
+        );
+        require!(
+            note.final_principal.is_none(),
+            BRCPriceAuthorityError::AlreadyFixed
+        );
+
+        let oracle_price = note.oracle_source.read_price(&ctx.accounts.oracle)?;
+        // fixing prices are compared in the scale of the strike
+        let final_fixing_price = rescale(
+            oracle_price.price as u64,
+            oracle_price.exponent,
+            note.price_exponent,
+            Rounding::Down,
+        )
+        .ok_or(BRCPriceAuthorityError::Overflow)?;
+
+        // payment dates are offsets to the issuance date
+        let fixing_date = ctx
+            .accounts
+            .structured_product
+            .issuance_date()
+            .ok_or(BRCPriceAuthorityError::NotIssued)?
+            + payment_date_offset;
+
+        validate_fixing_timestamp(
+            oracle_price.publish_time,
+            fixing_date,
+            note.fixing_window,
+            note.max_staleness,
+            Clock::get()?.unix_timestamp,
+        )?;
+
+        let final_principal = calc_capital_protected_final_principal(
+            note.initial_principal,
+            note.strike,
+            note.protection_in_basis_points,
+            note.participation_in_basis_points,
+            note.cap_in_basis_points,
+            final_fixing_price,
+        );
+
+        // investor payouts are rounded down
+        let final_principal = convert_quote_to_payment(
+            final_principal,
+            note.quote_decimals,
+            note.payment_decimals,
+            Rounding::Down,
+        )
+        .ok_or(BRCPriceAuthorityError::Overflow)?;
+
+        msg!("Finalizing capital protected note at {}", note.key());
+        msg!(
+            "Initial principal: {}, strike: {}, final_fixing_price: {}, Final principal: {}",
+            note.initial_principal,
+            note.strike,
+            final_fixing_price,
+            final_principal
+        );
+
+        let cpi_program = ctx.accounts.structured_product_program.to_account_info();
+
+        let cpi_accounts = SetPaymentPrice {
+            authority: ctx.accounts.capital_protected_note.to_account_info(),
+            payment: ctx.accounts.payment.to_account_info(),
+            structured_product: ctx.accounts.structured_product.to_account_info(),
+        };
+
+        let structured_product_key = ctx.accounts.structured_product.key();
+
+        let seeds = &[
+            b"capital-protected".as_ref(),
+            structured_product_key.as_ref(),
+            &[ctx.accounts.capital_protected_note.bump],
+        ];
+
+        set_payment_price(
+            CpiContext::new_with_signer(cpi_program, cpi_accounts, &[&seeds[..]]),
+            payment_date_offset,
+            final_principal,
+        )?;
+
+        let note = &mut ctx.accounts.capital_protected_note;
+
+        note.final_underlying_fixing_price = Some(final_fixing_price);
+        note.final_principal = Some(final_principal);
+        note.final_fixing_date = Some(Clock::get()?.unix_timestamp);
         Ok(())
     }
 }
@@ -132,8 +962,11 @@ pub struct Initialize<'info> {
     // TODO: space calc
     #[account(init, seeds=[structured_product.key().as_ref()], bump, payer=authority, space=500)]
     pub brc: Account<'info, BarrierReverseConvertible>,
-    #[account(seeds=[authority.key().as_ref(), underlying_symbol.as_bytes()], bump=dummy_oracle.bump, seeds::program=dummy_oracle_program)]
-    pub dummy_oracle: Account<'info, DummyOracleAccount>,
+    /// CHECK: decoded by the oracle source, dummy oracles are checked against the seeds
+    pub oracle: AccountInfo<'info>,
+    pub quote_currency_mint: InterfaceAccount<'info, Mint>,
+    pub payment_mint: InterfaceAccount<'info, Mint>,
+    pub delivery_mint: Option<InterfaceAccount<'info, Mint>>,
     pub dummy_oracle_program: Program<'info, DummyOracle>,
     pub structured_product_program: Program<'info, StructuredProduct>,
     pub system_program: Program<'info, System>,
@@ -146,27 +979,225 @@ pub struct SetFinalFixingPrice<'info> {
     pub brc: Account<'info, BarrierReverseConvertible>,
     pub structured_product: Account<'info, StructuredProductConfig>,
     pub payment: Account<'info, Payment>,
-    pub dummy_oracle: Account<'info, DummyOracleAccount>,
+    /// CHECK: decoded by the oracle source
+    #[account(address=brc.oracle @ BRCPriceAuthorityError::InvalidOracle)]
+    pub oracle: AccountInfo<'info>,
     pub dummy_oracle_program: Program<'info, DummyOracle>,
     pub structured_product_program: Program<'info, StructuredProduct>,
 }
 
+#[derive(Accounts)]
+pub struct ObserveBarrier<'info> {
+    pub payer: Signer<'info>,
+    #[account(mut, seeds=[structured_product.key().as_ref()], bump=brc.bump)]
+    pub brc: Account<'info, BarrierReverseConvertible>,
+    pub structured_product: Account<'info, StructuredProductConfig>,
+    /// CHECK: decoded by the oracle source
+    #[account(address=brc.oracle @ BRCPriceAuthorityError::InvalidOracle)]
+    pub oracle: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(underlying_symbols: Vec<String>, payment_date_offset: i64)]
+pub struct InitializeWorstOf<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account()]
+    pub structured_product: Account<'info, StructuredProductConfig>,
+    /// CHECK:
+    #[account(seeds=[structured_product.key().as_ref(), &[true.into()], &payment_date_offset.to_le_bytes()],
+    bump,
+    seeds::program=structured_product_program)]
+    pub payment: AccountInfo<'info>,
+    #[account(init,
+    seeds=[b"worst-of", structured_product.key().as_ref()],
+    bump,
+    payer=authority,
+    space=WorstOfBarrierReverseConvertible::space(&underlying_symbols))]
+    pub worst_of_brc: Account<'info, WorstOfBarrierReverseConvertible>,
+    pub quote_currency_mint: InterfaceAccount<'info, Mint>,
+    pub payment_mint: InterfaceAccount<'info, Mint>,
+    pub dummy_oracle_program: Program<'info, DummyOracle>,
+    pub structured_product_program: Program<'info, StructuredProduct>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetWorstOfFinalFixingPrice<'info> {
+    pub payer: Signer<'info>,
+    #[account(mut, seeds=[b"worst-of", structured_product.key().as_ref()], bump=worst_of_brc.bump)]
+    pub worst_of_brc: Account<'info, WorstOfBarrierReverseConvertible>,
+    pub structured_product: Account<'info, StructuredProductConfig>,
+    pub payment: Account<'info, Payment>,
+    pub structured_product_program: Program<'info, StructuredProduct>,
+}
+
+#[derive(Accounts)]
+#[instruction(underlying_symbol: String, payment_date_offset: i64)]
+pub struct InitializeCapitalProtected<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account()]
+    pub structured_product: Account<'info, StructuredProductConfig>,
+    /// CHECK:
+    #[account(seeds=[structured_product.key().as_ref(), &[true.into()], &payment_date_offset.to_le_bytes()],
+    bump,
+    seeds::program=structured_product_program)]
+    pub payment: AccountInfo<'info>,
+    #[account(init,
+    seeds=[b"capital-protected", structured_product.key().as_ref()],
+    bump,
+    payer=authority,
+    space=CapitalProtectedNote::space(underlying_symbol.len()))]
+    pub capital_protected_note: Account<'info, CapitalProtectedNote>,
+    /// CHECK: decoded by the oracle source, dummy oracles are checked against the seeds
+    pub oracle: AccountInfo<'info>,
+    pub quote_currency_mint: InterfaceAccount<'info, Mint>,
+    pub payment_mint: InterfaceAccount<'info, Mint>,
+    pub structured_product_program: Program<'info, StructuredProduct>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetCapitalProtectedFinalFixingPrice<'info> {
+    pub payer: Signer<'info>,
+    #[account(mut,
+    seeds=[b"capital-protected", structured_product.key().as_ref()],
+    bump=capital_protected_note.bump)]
+    pub capital_protected_note: Account<'info, CapitalProtectedNote>,
+    pub structured_product: Account<'info, StructuredProductConfig>,
+    pub payment: Account<'info, Payment>,
+    /// CHECK: decoded by the oracle source
+    #[account(address=capital_protected_note.oracle @ BRCPriceAuthorityError::InvalidOracle)]
+    pub oracle: AccountInfo<'info>,
+    pub structured_product_program: Program<'info, StructuredProduct>,
+}
+
 #[account]
 #[derive(Debug)]
 pub struct BarrierReverseConvertible {
     pub authority: Pubkey,
     pub underlying_symbol: String,
     pub initial_principal: u64,
+    // initial and final fixing prices and the barrier are in 10^price_exponent units
     pub initial_fixing_price: u64,
     pub barrier: u64,
-    pub dummy_oracle: Pubkey,
+    pub price_exponent: i32,
+    // initial principal in quote currency units, final principal in payment mint units
+    pub quote_decimals: u8,
+    pub payment_mint: Pubkey,
+    pub payment_decimals: u8,
+    // physical settlement on knock-in, delivering whole tokens of the delivery mint
+    pub delivery_mint: Option<Pubkey>,
+    pub delivery_decimals: u8,
+    pub oracle: Pubkey,
+    pub oracle_source: OracleSource,
     pub final_underlying_fixing_price: Option<u64>,
     pub final_fixing_date: Option<i64>,
     pub final_principal: Option<u64>,
+    pub final_delivery_per_unit: Option<u64>,
+    pub target_payment: Pubkey,
+    pub continuous_barrier: bool,
+    pub knocked_in: bool,
+    pub knock_in_date: Option<i64>,
+    pub max_staleness: i64,
+    pub fixing_window: i64,
+    pub bump: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct WorstOfUnderlying {
+    pub underlying_symbol: String,
+    pub oracle: Pubkey,
+    pub initial_fixing_price: u64,
+    pub barrier: u64,
+    pub final_fixing_price: Option<u64>,
+}
+
+#[account]
+#[derive(Debug)]
+pub struct WorstOfBarrierReverseConvertible {
+    pub authority: Pubkey,
+    pub initial_principal: u64,
+    pub underlyings: Vec<WorstOfUnderlying>,
+    // initial and final fixing prices are in 10^price_exponent units
+    pub price_exponent: i32,
+    // initial principal in quote currency units, final principal in payment mint units
+    pub quote_decimals: u8,
+    pub payment_mint: Pubkey,
+    pub payment_decimals: u8,
+    pub oracle_source: OracleSource,
+    pub max_staleness: i64,
+    pub fixing_window: i64,
+    pub final_fixing_date: Option<i64>,
+    pub final_principal: Option<u64>,
     pub target_payment: Pubkey,
     pub bump: u8,
 }
 
+impl WorstOfBarrierReverseConvertible {
+    pub fn space(underlying_symbols: &[String]) -> usize {
+        let underlyings: usize = underlying_symbols
+            .iter()
+            .map(|symbol| 4 + symbol.len() + 32 + 8 + 8 + 9)
+            .sum();
+        8 + 32 + 8 + 4 + underlyings + 4 + 1 + 32 + 1 + 1 + 8 + 8 + 9 + 9 + 32 + 1
+    }
+}
+
+#[account]
+#[derive(Debug)]
+pub struct CapitalProtectedNote {
+    pub authority: Pubkey,
+    pub underlying_symbol: String,
+    pub initial_principal: u64,
+    // strike and final fixing price are in 10^price_exponent units
+    pub strike: u64,
+    pub price_exponent: i32,
+    pub protection_in_basis_points: u64,
+    pub participation_in_basis_points: u64,
+    pub cap_in_basis_points: u64,
+    // initial principal in quote currency units, final principal in payment mint units
+    pub quote_decimals: u8,
+    pub payment_mint: Pubkey,
+    pub payment_decimals: u8,
+    pub oracle: Pubkey,
+    pub oracle_source: OracleSource,
+    pub max_staleness: i64,
+    pub fixing_window: i64,
+    pub final_underlying_fixing_price: Option<u64>,
+    pub final_fixing_date: Option<i64>,
+    pub final_principal: Option<u64>,
+    pub target_payment: Pubkey,
+    pub bump: u8,
+}
+
+impl CapitalProtectedNote {
+    pub fn space(underlying_symbol_len_in_bytes: usize) -> usize {
+        8 + 32
+            + 4
+            + underlying_symbol_len_in_bytes
+            + 8
+            + 8
+            + 4
+            + 8
+            + 8
+            + 8
+            + 1
+            + 32
+            + 1
+            + 32
+            + 1
+            + 8
+            + 8
+            + 9
+            + 9
+            + 9
+            + 32
+            + 1
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -184,6 +1215,14 @@ mod tests {
                         final_fixing_price,
                     );
                     assert_eq!(result, expected);
+
+                    // same payoff as expression
+                    let payoff = payoff::barrier_reverse_convertible(
+                        initial_principal,
+                        initial_fixing_price,
+                        barrier,
+                    );
+                    assert_eq!(payoff.evaluate(&[final_fixing_price]), Ok(expected));
                 }
             )*
         }
@@ -208,4 +1247,345 @@ mod tests {
         final_principal_price_test_15: (100000, 42000, 0, 0, 0,),
 
     }
+
+    macro_rules! mul_div_tests {
+        ($($name:ident: $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (a, b, c, rounding, expected) = $expected;
+                    let result = mul_div(a, b, c, rounding);
+                    assert_eq!(result, expected);
+                }
+            )*
+        }
+    }
+
+    #[cfg(test)]
+    mul_div_tests! {
+        mul_div_test_1: (100000, 30000, 42000, Rounding::Down, Some(71428),),
+        mul_div_test_2: (100000, 30000, 42000, Rounding::Up, Some(71429),),
+        mul_div_test_3: (100000, 33600, 42000, Rounding::Down, Some(80000),),
+        mul_div_test_4: (100000, 33600, 42000, Rounding::Up, Some(80000),),
+        mul_div_test_5: (u64::MAX, u64::MAX, u64::MAX, Rounding::Up, Some(u64::MAX),),
+        mul_div_test_6: (u64::MAX, 2, 1, Rounding::Down, None,),
+        mul_div_test_7: (100000, 30000, 0, Rounding::Down, None,),
+    }
+
+    macro_rules! rescale_tests {
+        ($($name:ident: $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (amount, from_exponent, to_exponent, rounding, expected) = $expected;
+                    let result = rescale(amount, from_exponent, to_exponent, rounding);
+                    assert_eq!(result, expected);
+                }
+            )*
+        }
+    }
+
+    #[cfg(test)]
+    rescale_tests! {
+        rescale_test_1: (4200012345678, -8, -2, Rounding::Down, Some(4200012),),
+        rescale_test_2: (4200012345678, -8, -2, Rounding::Up, Some(4200013),),
+        rescale_test_3: (4200000000000, -8, -2, Rounding::Up, Some(4200000),),
+        rescale_test_4: (42000, 0, -2, Rounding::Down, Some(4200000),),
+        rescale_test_5: (42000, -2, -2, Rounding::Up, Some(42000),),
+        rescale_test_6: (42000, -2, 0, Rounding::Down, Some(420),),
+        rescale_test_7: (42099, -2, 0, Rounding::Down, Some(420),),
+        rescale_test_8: (42001, -2, 0, Rounding::Up, Some(421),),
+        rescale_test_9: (u64::MAX, 0, -1, Rounding::Down, None,),
+        rescale_test_10: (0, 0, -30, Rounding::Down, Some(0),),
+        rescale_test_11: (1, -40, 0, Rounding::Down, Some(0),),
+        rescale_test_12: (1, -40, 0, Rounding::Up, Some(1),),
+    }
+
+    macro_rules! convert_quote_to_payment_tests {
+        ($($name:ident: $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (amount, quote_decimals, payment_decimals, rounding, expected) = $expected;
+                    let result = convert_quote_to_payment(amount, quote_decimals, payment_decimals, rounding);
+                    assert_eq!(result, expected);
+                }
+            )*
+        }
+    }
+
+    #[cfg(test)]
+    convert_quote_to_payment_tests! {
+        convert_quote_to_payment_test_1: (71428571, 6, 6, Rounding::Down, Some(71428571),),
+        convert_quote_to_payment_test_2: (71428571, 6, 9, Rounding::Down, Some(71428571000),),
+        convert_quote_to_payment_test_3: (71428571, 6, 2, Rounding::Down, Some(7142),),
+        convert_quote_to_payment_test_4: (71428571, 6, 2, Rounding::Up, Some(7143),),
+        convert_quote_to_payment_test_5: (71428571, 6, 0, Rounding::Down, Some(71),),
+        convert_quote_to_payment_test_6: (u64::MAX, 0, 9, Rounding::Down, None,),
+    }
+
+    // Oracle price with 8 decimals, initial fixing price in cents, principal in a 6 decimals
+    // quote currency, paid out in mints with different decimals
+    macro_rules! normalized_final_principal_tests {
+        ($($name:ident: $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (oracle_price, oracle_exponent, payment_decimals, expected) = $expected;
+                    let final_fixing_price = rescale(oracle_price, oracle_exponent, -2, Rounding::Down).unwrap();
+                    let final_principal = calc_final_principal(100000000, 4200000, 3360000, final_fixing_price);
+                    let result = convert_quote_to_payment(final_principal, 6, payment_decimals, Rounding::Down);
+                    assert_eq!(result, expected);
+                }
+            )*
+        }
+    }
+
+    #[cfg(test)]
+    normalized_final_principal_tests! {
+        normalized_final_principal_test_1: (5500000000000, -8, 6, Some(100000000),),
+        normalized_final_principal_test_2: (3360000000000, -8, 6, Some(80000000),),
+        normalized_final_principal_test_3: (3360000999999, -8, 6, Some(80000000),),
+        normalized_final_principal_test_4: (3000000000000, -8, 6, Some(71428571),),
+        normalized_final_principal_test_5: (3000000000000, -8, 9, Some(71428571000),),
+        normalized_final_principal_test_6: (3000000000000, -8, 2, Some(7142),),
+        normalized_final_principal_test_7: (30000, 0, 6, Some(71428571),),
+        normalized_final_principal_test_8: (30000000, -3, 6, Some(71428571),),
+    }
+
+    macro_rules! validate_fixing_settings_tests {
+        ($($name:ident: $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (max_staleness, fixing_window, expected) = $expected;
+                    let result = validate_fixing_settings(max_staleness, fixing_window);
+                    assert_eq!(result.is_ok(), expected);
+                }
+            )*
+        }
+    }
+
+    #[cfg(test)]
+    validate_fixing_settings_tests! {
+        validate_fixing_settings_test_1: (3600, 60, true,),
+        validate_fixing_settings_test_2: (1, 0, true,),
+        validate_fixing_settings_test_3: (0, 60, false,),
+        validate_fixing_settings_test_4: (-1, 60, false,),
+        validate_fixing_settings_test_5: (3600, -1, false,),
+    }
+
+    macro_rules! validate_fixing_timestamp_tests {
+        ($($name:ident: $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (last_update, fixing_date, fixing_window, max_staleness, current_time, expected) = $expected;
+                    let result = validate_fixing_timestamp(
+                        last_update,
+                        fixing_date,
+                        fixing_window,
+                        max_staleness,
+                        current_time,
+                    );
+                    assert_eq!(result.is_ok(), expected);
+                }
+            )*
+        }
+    }
+
+    #[cfg(test)]
+    validate_fixing_timestamp_tests! {
+        validate_fixing_timestamp_test_1: (1000, 1000, 0, 0, 1000, true,),
+        validate_fixing_timestamp_test_2: (1000, 1000, 60, 3600, 1200, true,),
+        validate_fixing_timestamp_test_3: (940, 1000, 60, 3600, 1200, true,),
+        validate_fixing_timestamp_test_4: (1060, 1000, 60, 3600, 1200, true,),
+        validate_fixing_timestamp_test_5: (939, 1000, 60, 3600, 1200, false,),
+        validate_fixing_timestamp_test_6: (1061, 1000, 60, 3600, 1200, false,),
+        validate_fixing_timestamp_test_7: (1000, 1000, 60, 3600, 4600, true,),
+        validate_fixing_timestamp_test_8: (1000, 1000, 60, 3600, 4601, false,),
+        validate_fixing_timestamp_test_9: (1000 - 14 * 86400, 1000, 86400, 30 * 86400, 1000, false,),
+    }
+
+    macro_rules! validate_observation_timestamp_tests {
+        ($($name:ident: $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (last_update, observation_date, fixing_window, max_staleness, current_time, expected) = $expected;
+                    let result = validate_observation_timestamp(
+                        last_update,
+                        observation_date,
+                        fixing_window,
+                        max_staleness,
+                        current_time,
+                    );
+                    assert_eq!(result.is_ok(), expected);
+                }
+            )*
+        }
+    }
+
+    #[cfg(test)]
+    validate_observation_timestamp_tests! {
+        validate_observation_timestamp_test_1: (1000, 1000, 0, 0, 1000, true,),
+        validate_observation_timestamp_test_2: (1030, 1000, 60, 3600, 1200, true,),
+        validate_observation_timestamp_test_3: (1060, 1000, 60, 3600, 1200, true,),
+        // observation date not reached yet
+        validate_observation_timestamp_test_4: (1000, 1000, 60, 3600, 999, false,),
+        validate_observation_timestamp_test_5: (900, 1000, 60, 3600, 950, false,),
+        // price published before the observation date
+        validate_observation_timestamp_test_6: (999, 1000, 60, 3600, 1200, false,),
+        validate_observation_timestamp_test_7: (940, 1000, 60, 3600, 1200, false,),
+        // price published too long after the observation date
+        validate_observation_timestamp_test_8: (1061, 1000, 60, 3600, 1200, false,),
+        // stale price
+        validate_observation_timestamp_test_9: (1000, 1000, 60, 3600, 4600, true,),
+        validate_observation_timestamp_test_10: (1000, 1000, 60, 3600, 4601, false,),
+    }
+
+    macro_rules! validate_barrier_observation_timestamp_tests {
+        ($($name:ident: $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (last_update, issuance_date, max_staleness, current_time, expected) = $expected;
+                    let result = validate_barrier_observation_timestamp(
+                        last_update,
+                        issuance_date,
+                        max_staleness,
+                        current_time,
+                    );
+                    assert_eq!(result.is_ok(), expected);
+                }
+            )*
+        }
+    }
+
+    #[cfg(test)]
+    validate_barrier_observation_timestamp_tests! {
+        validate_barrier_observation_timestamp_test_1: (1000, 1000, 0, 1000, true,),
+        validate_barrier_observation_timestamp_test_2: (5000, 1000, 60, 5060, true,),
+        validate_barrier_observation_timestamp_test_3: (999, 1000, 3600, 1200, false,),
+        validate_barrier_observation_timestamp_test_4: (5000, 1000, 60, 5061, false,),
+    }
+
+    macro_rules! calc_knocked_in_final_principal_tests {
+        ($($name:ident: $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (initial_principal, initial_fixing_price, final_fixing_price, expected) = $expected;
+                    let result = calc_knocked_in_final_principal(
+                        initial_principal,
+                        initial_fixing_price,
+                        final_fixing_price,
+                    );
+                    assert_eq!(result, expected);
+                }
+            )*
+        }
+    }
+
+    #[cfg(test)]
+    calc_knocked_in_final_principal_tests! {
+        knocked_in_final_principal_test_1: (100000, 42000, 55000, 100000,),
+        knocked_in_final_principal_test_2: (100000, 42000, 42000, 100000,),
+        knocked_in_final_principal_test_3: (100000, 42000, 41999, 99997,),
+        knocked_in_final_principal_test_4: (100000, 42000, 33600, 80000,),
+        knocked_in_final_principal_test_5: (100000000000, 42000000000, 30000000000, 71428571428,),
+        knocked_in_final_principal_test_6: (100000, 42000, 0, 0,),
+    }
+
+    macro_rules! calc_worst_of_final_principal_tests {
+        ($($name:ident: $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (initial_principal, underlyings, expected) = $expected;
+                    let result = calc_worst_of_final_principal(initial_principal, &underlyings);
+                    assert_eq!(result, expected);
+                }
+            )*
+        }
+    }
+
+    #[cfg(test)]
+    calc_worst_of_final_principal_tests! {
+        worst_of_final_principal_test_1: (100000, [(42000, 29400, 55000), (100, 70, 120)], 100000,),
+        worst_of_final_principal_test_2: (100000, [(42000, 29400, 30000), (100, 70, 90)], 100000,),
+        worst_of_final_principal_test_3: (100000, [(42000, 29400, 29400), (100, 70, 90)], 70000,),
+        worst_of_final_principal_test_4: (100000, [(42000, 29400, 42000), (100, 70, 50)], 50000,),
+        worst_of_final_principal_test_5: (100000, [(42000, 29400, 21000), (100, 70, 60), (10, 7, 8)], 50000,),
+        worst_of_final_principal_test_6: (100000, [(42000, 29400, 21000), (100, 70, 40), (10, 7, 8)], 40000,),
+        worst_of_final_principal_test_7: (100000, [(42000, 29400, 0), (100, 70, 120)], 0,),
+        worst_of_final_principal_test_8: (100000, [(42000, 42000, 42000), (100, 100, 100)], 100000,),
+        worst_of_final_principal_test_9: (100000000000, [(42000000000, 33600000000, 30000000000), (100, 80, 95), (5, 4, 5), (7, 5, 7), (9, 6, 9)], 71428571428,),
+        worst_of_final_principal_test_10: (100000, [(42000, 0, 30000), (100, 70, 71)], 100000,),
+    }
+
+    macro_rules! calc_physical_delivery_tests {
+        ($($name:ident: $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (initial_principal, initial_fixing_price, final_fixing_price, expected) = $expected;
+                    let result = calc_physical_delivery(
+                        initial_principal,
+                        initial_fixing_price,
+                        final_fixing_price,
+                    );
+                    assert_eq!(result, expected);
+                }
+            )*
+        }
+    }
+
+    #[cfg(test)]
+    calc_physical_delivery_tests! {
+        physical_delivery_test_1: (100000, 42000, 30000, Some((2, 11428)),),
+        physical_delivery_test_2: (100000, 50000, 30000, Some((2, 0)),),
+        physical_delivery_test_3: (100000, 42000, 0, Some((2, 0)),),
+        physical_delivery_test_4: (100000, 200000, 150000, Some((0, 75000)),),
+        physical_delivery_test_5: (100000000000, 42000000000, 29400000000, Some((2, 11200000000)),),
+        physical_delivery_test_6: (100000, 0, 30000, None,),
+    }
+
+    macro_rules! calc_capital_protected_final_principal_tests {
+        ($($name:ident: $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (initial_principal, strike, protection_in_basis_points, participation_in_basis_points, cap_in_basis_points, final_fixing_price, expected) = $expected;
+                    let result = calc_capital_protected_final_principal(
+                        initial_principal,
+                        strike,
+                        protection_in_basis_points,
+                        participation_in_basis_points,
+                        cap_in_basis_points,
+                        final_fixing_price,
+                    );
+                    assert_eq!(result, expected);
+                }
+            )*
+        }
+    }
+
+    #[cfg(test)]
+    calc_capital_protected_final_principal_tests! {
+        capital_protected_final_principal_test_1: (100000, 42000, 10000, 10000, 13000, 42000, 100000,),
+        capital_protected_final_principal_test_2: (100000, 42000, 10000, 10000, 13000, 46200, 110000,),
+        capital_protected_final_principal_test_3: (100000, 42000, 10000, 10000, 13000, 54600, 130000,),
+        capital_protected_final_principal_test_4: (100000, 42000, 10000, 10000, 13000, 84000, 130000,),
+        capital_protected_final_principal_test_5: (100000, 42000, 10000, 10000, 13000, 21000, 100000,),
+        capital_protected_final_principal_test_6: (100000, 42000, 10000, 10000, 13000, 0, 100000,),
+        capital_protected_final_principal_test_7: (100000, 42000, 10000, 5000, 13000, 50400, 110000,),
+        capital_protected_final_principal_test_8: (100000, 42000, 10000, 15000, 13000, 46200, 115000,),
+        capital_protected_final_principal_test_9: (100000, 42000, 9000, 10000, 13000, 39900, 95000,),
+        capital_protected_final_principal_test_10: (100000, 42000, 9000, 10000, 13000, 21000, 90000,),
+        capital_protected_final_principal_test_11: (100000, 42000, 10000, 0, 13000, 84000, 100000,),
+        capital_protected_final_principal_test_12: (100000, 42000, 10000, 10000, 10000, 84000, 100000,),
+        capital_protected_final_principal_test_13: (100000, 42000, 10000, 10000, 13000, 42001, 100002,),
+        capital_protected_final_principal_test_14: (100000000000, 42000000000, 10000, 10000, 13000, 43000000000, 102380952380,),
+    }
 }
@@ -0,0 +1,319 @@
+use aggregated_oracle::AggregatedOracleAccount;
+use anchor_lang::prelude::*;
+use dummy_oracle::DummyOracleAccount;
+
+use crate::BRCPriceAuthorityError;
+
+// Price as read from an oracle account, the value is price * 10^exponent
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OraclePrice {
+    pub price: i64,
+    pub exponent: i32,
+    pub confidence: u64,
+    pub publish_time: i64,
+}
+
+pub trait PriceReader {
+    fn read_price(data: &[u8]) -> Result<OraclePrice>;
+}
+
+pub struct DummyOracleReader;
+
+impl PriceReader for DummyOracleReader {
+    fn read_price(data: &[u8]) -> Result<OraclePrice> {
+        let dummy_oracle = DummyOracleAccount::try_deserialize(&mut &data[..])?;
+        Ok(OraclePrice {
+            price: i64::try_from(dummy_oracle.current_price)
+                .map_err(|_| BRCPriceAuthorityError::InvalidOraclePrice)?,
+            exponent: dummy_oracle.exponent,
+            confidence: 0,
+            publish_time: dummy_oracle.last_update,
+        })
+    }
+}
+
+// Reads the median of the publisher submissions, not usable before the first aggregation
+pub struct AggregatedOracleReader;
+
+impl PriceReader for AggregatedOracleReader {
+    fn read_price(data: &[u8]) -> Result<OraclePrice> {
+        let aggregated_oracle = AggregatedOracleAccount::try_deserialize(&mut &data[..])?;
+        require!(
+            aggregated_oracle.last_update > 0,
+            BRCPriceAuthorityError::InvalidOraclePrice
+        );
+        Ok(OraclePrice {
+            price: i64::try_from(aggregated_oracle.aggregate_price)
+                .map_err(|_| BRCPriceAuthorityError::InvalidOraclePrice)?,
+            exponent: aggregated_oracle.exponent,
+            confidence: aggregated_oracle.confidence,
+            publish_time: aggregated_oracle.last_update,
+        })
+    }
+}
+
+// Reads the aggregate price of a Pyth (v2) price account.
+// Only the fields needed for fixings are decoded, see the offsets below.
+pub struct PythPriceReader;
+
+// Pyth oracle program on mainnet-beta, owner of the price accounts
+pub mod pyth_program {
+    use anchor_lang::prelude::*;
+
+    declare_id!("FsJ3A3u2vn5cTVofAjvy6y5kwABJAqYWpe4975bi2epH");
+}
+
+pub const PYTH_PROGRAM_ID: Pubkey = pyth_program::ID;
+
+pub const PYTH_MAGIC: u32 = 0xa1b2c3d4;
+pub const PYTH_VERSION: u32 = 2;
+pub const PYTH_ACCOUNT_TYPE_PRICE: u32 = 3;
+pub const PYTH_STATUS_TRADING: u32 = 1;
+
+const PYTH_MAGIC_OFFSET: usize = 0;
+const PYTH_VERSION_OFFSET: usize = 4;
+const PYTH_ACCOUNT_TYPE_OFFSET: usize = 8;
+const PYTH_EXPONENT_OFFSET: usize = 20;
+const PYTH_TIMESTAMP_OFFSET: usize = 96;
+const PYTH_AGGREGATE_PRICE_OFFSET: usize = 208;
+const PYTH_AGGREGATE_CONFIDENCE_OFFSET: usize = 216;
+const PYTH_AGGREGATE_STATUS_OFFSET: usize = 224;
+pub const PYTH_PRICE_ACCOUNT_MIN_LEN: usize = 240;
+
+fn read_bytes<const N: usize>(data: &[u8], offset: usize) -> [u8; N] {
+    data[offset..offset + N].try_into().unwrap()
+}
+
+impl PriceReader for PythPriceReader {
+    fn read_price(data: &[u8]) -> Result<OraclePrice> {
+        require!(
+            data.len() >= PYTH_PRICE_ACCOUNT_MIN_LEN,
+            BRCPriceAuthorityError::InvalidOracle
+        );
+        require!(
+            u32::from_le_bytes(read_bytes(data, PYTH_MAGIC_OFFSET)) == PYTH_MAGIC
+                && u32::from_le_bytes(read_bytes(data, PYTH_VERSION_OFFSET)) == PYTH_VERSION
+                && u32::from_le_bytes(read_bytes(data, PYTH_ACCOUNT_TYPE_OFFSET))
+                    == PYTH_ACCOUNT_TYPE_PRICE,
+            BRCPriceAuthorityError::InvalidOracle
+        );
+        require!(
+            u32::from_le_bytes(read_bytes(data, PYTH_AGGREGATE_STATUS_OFFSET))
+                == PYTH_STATUS_TRADING,
+            BRCPriceAuthorityError::InvalidOraclePrice
+        );
+
+        Ok(OraclePrice {
+            price: i64::from_le_bytes(read_bytes(data, PYTH_AGGREGATE_PRICE_OFFSET)),
+            exponent: i32::from_le_bytes(read_bytes(data, PYTH_EXPONENT_OFFSET)),
+            confidence: u64::from_le_bytes(read_bytes(data, PYTH_AGGREGATE_CONFIDENCE_OFFSET)),
+            publish_time: i64::from_le_bytes(read_bytes(data, PYTH_TIMESTAMP_OFFSET)),
+        })
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OracleSource {
+    DummyOracle,
+    Pyth,
+    AggregatedOracle,
+}
+
+impl OracleSource {
+    // Dummy oracles have to be the oracle of the authority for the symbol,
+    // any oracle has to be readable with the source
+    pub fn validate_oracle(
+        &self,
+        oracle: &AccountInfo,
+        authority: &Pubkey,
+        underlying_symbol: &str,
+    ) -> Result<()> {
+        if self == &OracleSource::DummyOracle {
+            let (dummy_oracle_key, _) = Pubkey::find_program_address(
+                &[authority.as_ref(), underlying_symbol.as_bytes()],
+                &dummy_oracle::ID,
+            );
+            require!(
+                oracle.key() == dummy_oracle_key,
+                BRCPriceAuthorityError::InvalidOracle
+            );
+        }
+        self.read_price(oracle)?;
+
+        Ok(())
+    }
+
+    pub fn read_price(&self, oracle: &AccountInfo) -> Result<OraclePrice> {
+        let data = oracle.try_borrow_data()?;
+        let oracle_price = match self {
+            OracleSource::DummyOracle => {
+                require!(
+                    oracle.owner == &dummy_oracle::ID,
+                    BRCPriceAuthorityError::InvalidOracle
+                );
+                DummyOracleReader::read_price(&data)?
+            }
+            OracleSource::Pyth => {
+                require!(
+                    oracle.owner == &PYTH_PROGRAM_ID,
+                    BRCPriceAuthorityError::InvalidOracle
+                );
+                PythPriceReader::read_price(&data)?
+            }
+            OracleSource::AggregatedOracle => {
+                require!(
+                    oracle.owner == &aggregated_oracle::ID,
+                    BRCPriceAuthorityError::InvalidOracle
+                );
+                AggregatedOracleReader::read_price(&data)?
+            }
+        };
+        require!(
+            oracle_price.price >= 0,
+            BRCPriceAuthorityError::InvalidOraclePrice
+        );
+        Ok(oracle_price)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pyth_price_account(price: i64, exponent: i32, confidence: u64, timestamp: i64) -> Vec<u8> {
+        let mut data = vec![0u8; 3312];
+        data[PYTH_MAGIC_OFFSET..PYTH_MAGIC_OFFSET + 4].copy_from_slice(&PYTH_MAGIC.to_le_bytes());
+        data[PYTH_VERSION_OFFSET..PYTH_VERSION_OFFSET + 4]
+            .copy_from_slice(&PYTH_VERSION.to_le_bytes());
+        data[PYTH_ACCOUNT_TYPE_OFFSET..PYTH_ACCOUNT_TYPE_OFFSET + 4]
+            .copy_from_slice(&PYTH_ACCOUNT_TYPE_PRICE.to_le_bytes());
+        data[PYTH_EXPONENT_OFFSET..PYTH_EXPONENT_OFFSET + 4]
+            .copy_from_slice(&exponent.to_le_bytes());
+        data[PYTH_TIMESTAMP_OFFSET..PYTH_TIMESTAMP_OFFSET + 8]
+            .copy_from_slice(&timestamp.to_le_bytes());
+        data[PYTH_AGGREGATE_PRICE_OFFSET..PYTH_AGGREGATE_PRICE_OFFSET + 8]
+            .copy_from_slice(&price.to_le_bytes());
+        data[PYTH_AGGREGATE_CONFIDENCE_OFFSET..PYTH_AGGREGATE_CONFIDENCE_OFFSET + 8]
+            .copy_from_slice(&confidence.to_le_bytes());
+        data[PYTH_AGGREGATE_STATUS_OFFSET..PYTH_AGGREGATE_STATUS_OFFSET + 4]
+            .copy_from_slice(&PYTH_STATUS_TRADING.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn read_pyth_price() {
+        let data = pyth_price_account(4200000000000, -8, 150000000, 1700000000);
+        let result = PythPriceReader::read_price(&data).unwrap();
+        assert_eq!(
+            result,
+            OraclePrice {
+                price: 4200000000000,
+                exponent: -8,
+                confidence: 150000000,
+                publish_time: 1700000000,
+            }
+        );
+    }
+
+    #[test]
+    fn read_pyth_price_rejects_invalid_magic() {
+        let mut data = pyth_price_account(4200000000000, -8, 150000000, 1700000000);
+        data[PYTH_MAGIC_OFFSET] = 0;
+        assert!(PythPriceReader::read_price(&data).is_err());
+    }
+
+    #[test]
+    fn read_pyth_price_rejects_non_price_account() {
+        let mut data = pyth_price_account(4200000000000, -8, 150000000, 1700000000);
+        data[PYTH_ACCOUNT_TYPE_OFFSET..PYTH_ACCOUNT_TYPE_OFFSET + 4]
+            .copy_from_slice(&2u32.to_le_bytes());
+        assert!(PythPriceReader::read_price(&data).is_err());
+    }
+
+    #[test]
+    fn read_pyth_price_rejects_halted_price() {
+        let mut data = pyth_price_account(4200000000000, -8, 150000000, 1700000000);
+        data[PYTH_AGGREGATE_STATUS_OFFSET..PYTH_AGGREGATE_STATUS_OFFSET + 4]
+            .copy_from_slice(&0u32.to_le_bytes());
+        assert!(PythPriceReader::read_price(&data).is_err());
+    }
+
+    #[test]
+    fn read_pyth_price_rejects_short_account() {
+        let data = pyth_price_account(4200000000000, -8, 150000000, 1700000000);
+        assert!(PythPriceReader::read_price(&data[..PYTH_PRICE_ACCOUNT_MIN_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn read_dummy_oracle_price() {
+        let dummy_oracle = DummyOracleAccount {
+            authority: Pubkey::new_unique(),
+            asset_symbol: "BTC".to_string(),
+            current_price: 42000,
+            exponent: -2,
+            quote_currency_mint: Pubkey::new_unique(),
+            last_update: 1700000000,
+            vendor: None,
+            bump: 255,
+        };
+        let mut data = Vec::new();
+        dummy_oracle.try_serialize(&mut data).unwrap();
+
+        let result = DummyOracleReader::read_price(&data).unwrap();
+        assert_eq!(
+            result,
+            OraclePrice {
+                price: 42000,
+                exponent: -2,
+                confidence: 0,
+                publish_time: 1700000000,
+            }
+        );
+    }
+
+    fn aggregated_oracle_account(aggregate_price: u64, last_update: i64) -> Vec<u8> {
+        let aggregated_oracle = AggregatedOracleAccount {
+            authority: Pubkey::new_unique(),
+            asset_symbol: "BTC".to_string(),
+            quote_currency_mint: Pubkey::new_unique(),
+            exponent: -6,
+            quorum: 2,
+            max_staleness: 60,
+            publishers: Vec::new(),
+            aggregate_price,
+            confidence: 100,
+            last_update,
+            bump: 255,
+        };
+        let mut data = Vec::new();
+        aggregated_oracle.try_serialize(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn read_aggregated_oracle_price() {
+        let data = aggregated_oracle_account(42000, 1700000000);
+        let result = AggregatedOracleReader::read_price(&data).unwrap();
+        assert_eq!(
+            result,
+            OraclePrice {
+                price: 42000,
+                exponent: -6,
+                confidence: 100,
+                publish_time: 1700000000,
+            }
+        );
+    }
+
+    #[test]
+    fn read_aggregated_oracle_price_rejects_missing_aggregate() {
+        let data = aggregated_oracle_account(0, 0);
+        assert!(AggregatedOracleReader::read_price(&data).is_err());
+    }
+
+    #[test]
+    fn read_dummy_oracle_price_rejects_pyth_account() {
+        let data = pyth_price_account(4200000000000, -8, 150000000, 1700000000);
+        assert!(DummyOracleReader::read_price(&data).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+use anchor_lang::prelude::*;
+use brc_price_authority::validate_observation_timestamp;
+use dummy_oracle::program::DummyOracle;
+use dummy_oracle::DummyOracleAccount;
+use structured_product::program::StructuredProduct;
+use structured_product::{Payment, StructuredProductConfig};
+
+declare_id!("EpdD9hTLnaPR3ocxoxx9hyDR4gyCimr5KzZmLHDqicWm");
+
+// Returns the coupon per unit paid at an observation and the number of missed coupons carried forward.
+// With the memory feature, a passing observation also pays all previously missed coupons.
+pub fn calc_conditional_coupon(
+    coupon_barrier: u64,
+    coupon_per_unit: u64,
+    memory: bool,
+    missed_coupons: u8,
+    fixing_price: u64,
+) -> (u64, u8) {
+    match fixing_price {
+        fixing_price if fixing_price >= coupon_barrier && memory => {
+            (coupon_per_unit * (missed_coupons as u64 + 1), 0)
+        }
+        fixing_price if fixing_price >= coupon_barrier => (coupon_per_unit, 0),
+        _ if memory => (0, missed_coupons + 1),
+        _ => (0, 0),
+    }
+}
+
+#[error_code]
+pub enum ConditionalCouponPriceAuthorityError {
+    #[msg("Unauthorized")]
+    Unauthorized,
+    #[msg("Invalid observation date")]
+    InvalidObservationDate,
+    #[msg("All observations done")]
+    ObservationsCompleted,
+    #[msg("Product not issued")]
+    NotIssued,
+}
+
+#[program]
+pub mod conditional_coupon_price_authority {
+    use super::*;
+    use structured_product::cpi::accounts::SetPaymentPrice;
+    use structured_product::cpi::set_payment_price;
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn initialize(
+        ctx: Context<Initialize>,
+        underlying_symbol: String,
+        observation_date_offsets: Vec<i64>,
+        initial_fixing_price: u64,
+        coupon_barrier_in_basis_points: u64,
+        coupon_per_unit: u64,
+        memory: bool,
+        max_staleness: i64,
+        fixing_window: i64,
+    ) -> Result<()> {
+        // observations and missed coupons are counted in a u8
+        require!(
+            !observation_date_offsets.is_empty()
+                && observation_date_offsets.len() <= u8::MAX as usize
+                && observation_date_offsets.windows(2).all(|w| w[0] < w[1]),
+            ConditionalCouponPriceAuthorityError::InvalidObservationDate
+        );
+
+        msg!(
+            "Initializing conditional coupons at {}",
+            ctx.accounts.conditional_coupons.key()
+        );
+        let conditional_coupons = &mut ctx.accounts.conditional_coupons;
+
+        conditional_coupons.authority = *ctx.accounts.authority.key;
+        conditional_coupons.underlying_symbol = underlying_symbol;
+        conditional_coupons.initial_fixing_price = initial_fixing_price;
+        // initial fixing prices scaled by the coupon_barrier_in_basis_points
+        conditional_coupons.coupon_barrier =
+            initial_fixing_price * coupon_barrier_in_basis_points / 10000;
+        conditional_coupons.coupon_per_unit = coupon_per_unit;
+        conditional_coupons.memory = memory;
+        conditional_coupons.dummy_oracle = ctx.accounts.dummy_oracle.key();
+        conditional_coupons.observation_date_offsets = observation_date_offsets;
+        conditional_coupons.max_staleness = max_staleness;
+        conditional_coupons.fixing_window = fixing_window;
+        conditional_coupons.next_observation = 0;
+        conditional_coupons.missed_coupons = 0;
+
+        conditional_coupons.bump = ctx.bumps.conditional_coupons;
+        msg!("Conditional coupons: {:?}", conditional_coupons);
+
+        Ok(())
+    }
+
+    // Observations have to be done in order, each one sets the price of the coupon payment
+    // at the observation date.
+    pub fn observe_coupon(ctx: Context<ObserveCoupon>, payment_date_offset: i64) -> Result<()> {
+        let conditional_coupons = &ctx.accounts.conditional_coupons;
+        let observation_index = conditional_coupons.next_observation as usize;
+
+        require!(
+            observation_index < conditional_coupons.observation_date_offsets.len(),
+            ConditionalCouponPriceAuthorityError::ObservationsCompleted
+        );
+        require!(
+            conditional_coupons.observation_date_offsets[observation_index] == payment_date_offset,
+            ConditionalCouponPriceAuthorityError::InvalidObservationDate
+        );
+        require!(
+            !ctx.accounts.payment.principal,
+            ConditionalCouponPriceAuthorityError::InvalidObservationDate
+        );
+
+        // observation dates are offsets to the issuance date
+        let observation_date = ctx
+            .accounts
+            .structured_product
+            .issuance_date()
+            .ok_or(ConditionalCouponPriceAuthorityError::NotIssued)?
+            + payment_date_offset;
+
+        validate_observation_timestamp(
+            ctx.accounts.dummy_oracle.last_update,
+            observation_date,
+            conditional_coupons.fixing_window,
+            conditional_coupons.max_staleness,
+            Clock::get()?.unix_timestamp,
+        )?;
+
+        let fixing_price = ctx.accounts.dummy_oracle.current_price;
+
+        let (coupon, missed_coupons) = calc_conditional_coupon(
+            conditional_coupons.coupon_barrier,
+            conditional_coupons.coupon_per_unit,
+            conditional_coupons.memory,
+            conditional_coupons.missed_coupons,
+            fixing_price,
+        );
+
+        msg!(
+            "Observation {} at {}, fixing_price: {}, coupon_barrier: {}, coupon: {}, missed coupons: {}",
+            observation_index,
+            payment_date_offset,
+            fixing_price,
+            conditional_coupons.coupon_barrier,
+            coupon,
+            missed_coupons
+        );
+
+        let cpi_program = ctx.accounts.structured_product_program.to_account_info();
+
+        let cpi_accounts = SetPaymentPrice {
+            authority: ctx.accounts.conditional_coupons.to_account_info(),
+            payment: ctx.accounts.payment.to_account_info(),
+            structured_product: ctx.accounts.structured_product.to_account_info(),
+        };
+
+        let structured_product_key = ctx.accounts.structured_product.key();
+
+        let seeds = &[
+            structured_product_key.as_ref(),
+            &[ctx.accounts.conditional_coupons.bump],
+        ];
+
+        set_payment_price(
+            CpiContext::new_with_signer(cpi_program, cpi_accounts, &[&seeds[..]]),
+            payment_date_offset,
+            coupon,
+        )?;
+
+        let conditional_coupons = &mut ctx.accounts.conditional_coupons;
+        conditional_coupons.next_observation += 1;
+        conditional_coupons.missed_coupons = missed_coupons;
+
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(underlying_symbol: String, observation_date_offsets: Vec<i64>)]
+pub struct Initialize<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account()]
+    pub structured_product: Account<'info, StructuredProductConfig>,
+    #[account(init,
+    seeds=[structured_product.key().as_ref()],
+    bump,
+    payer=authority,
+    space=ConditionalCoupons::space(underlying_symbol.len(), observation_date_offsets.len()))]
+    pub conditional_coupons: Account<'info, ConditionalCoupons>,
+    #[account(seeds=[authority.key().as_ref(), underlying_symbol.as_bytes()], bump=dummy_oracle.bump, seeds::program=dummy_oracle_program)]
+    pub dummy_oracle: Account<'info, DummyOracleAccount>,
+    pub dummy_oracle_program: Program<'info, DummyOracle>,
+    pub structured_product_program: Program<'info, StructuredProduct>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(payment_date_offset: i64)]
+pub struct ObserveCoupon<'info> {
+    pub payer: Signer<'info>,
+    #[account(mut, seeds=[structured_product.key().as_ref()], bump=conditional_coupons.bump)]
+    pub conditional_coupons: Account<'info, ConditionalCoupons>,
+    pub structured_product: Account<'info, StructuredProductConfig>,
+    #[account(mut,
+    seeds=[structured_product.key().as_ref(), &[false.into()], &payment_date_offset.to_le_bytes()],
+    bump=payment.bump,
+    seeds::program=structured_product_program)]
+    pub payment: Account<'info, Payment>,
+    #[account(address=conditional_coupons.dummy_oracle @ ConditionalCouponPriceAuthorityError::Unauthorized)]
+    pub dummy_oracle: Account<'info, DummyOracleAccount>,
+    pub structured_product_program: Program<'info, StructuredProduct>,
+}
+
+#[account]
+#[derive(Debug)]
+pub struct ConditionalCoupons {
+    pub authority: Pubkey,
+    pub underlying_symbol: String,
+    pub initial_fixing_price: u64,
+    pub coupon_barrier: u64,
+    pub coupon_per_unit: u64,
+    pub memory: bool,
+    pub dummy_oracle: Pubkey,
+    pub observation_date_offsets: Vec<i64>,
+    pub max_staleness: i64,
+    pub fixing_window: i64,
+    pub next_observation: u8,
+    pub missed_coupons: u8,
+    pub bump: u8,
+}
+
+impl ConditionalCoupons {
+    pub fn space(underlying_symbol_len_in_bytes: usize, num_observations: usize) -> usize {
+        8 + 32
+            + 4
+            + underlying_symbol_len_in_bytes
+            + 8
+            + 8
+            + 8
+            + 1
+            + 32
+            + 4
+            + 8 * num_observations
+            + 8
+            + 8
+            + 1
+            + 1
+            + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! calc_conditional_coupon_tests {
+        ($($name:ident: $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (coupon_barrier, coupon_per_unit, memory, missed_coupons, fixing_price, expected) = $expected;
+                    let result = calc_conditional_coupon(
+                        coupon_barrier,
+                        coupon_per_unit,
+                        memory,
+                        missed_coupons,
+                        fixing_price,
+                    );
+                    assert_eq!(result, expected);
+                }
+            )*
+        }
+    }
+
+    #[cfg(test)]
+    calc_conditional_coupon_tests! {
+        conditional_coupon_test_1: (29400, 500, false, 0, 42000, (500, 0),),
+        conditional_coupon_test_2: (29400, 500, false, 0, 29400, (500, 0),),
+        conditional_coupon_test_3: (29400, 500, false, 0, 29399, (0, 0),),
+        conditional_coupon_test_4: (29400, 500, false, 0, 0, (0, 0),),
+        conditional_coupon_test_5: (29400, 500, true, 0, 42000, (500, 0),),
+        conditional_coupon_test_6: (29400, 500, true, 0, 29399, (0, 1),),
+        conditional_coupon_test_7: (29400, 500, true, 1, 29399, (0, 2),),
+        conditional_coupon_test_8: (29400, 500, true, 2, 29400, (1500, 0),),
+        conditional_coupon_test_9: (29400, 500, true, 3, 55000, (2000, 0),),
+        conditional_coupon_test_10: (0, 500, false, 0, 0, (500, 0),),
+        conditional_coupon_test_11: (29400, 500, true, 254, 29399, (0, 255),),
+    }
+}
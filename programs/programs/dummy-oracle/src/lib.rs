@@ -1,8 +1,110 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::ed25519_program;
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::solana_program::sysvar::instructions::{
+    load_current_index_checked, load_instruction_at_checked,
+};
 use anchor_spl::token_interface::Mint;
 
 declare_id!("BZuknSg2JQVQFt4d6a5eHsoeivEMVp4tvSnkas8yoxgY");
 
+pub const PRICE_HISTORY_CAPACITY: usize = 64;
+
+// Layout of the Ed25519 native program instruction data, see solana_sdk::ed25519_instruction
+const ED25519_SIGNATURE_OFFSETS_START: usize = 2;
+const ED25519_SIGNATURE_OFFSETS_SIZE: usize = 14;
+const ED25519_PUBKEY_SIZE: usize = 32;
+// instruction index of the offsets when the data is part of the Ed25519 instruction itself
+const ED25519_CURRENT_INSTRUCTION: u16 = u16::MAX;
+
+// Message signed off-chain by the price vendor, borsh serialized
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PriceAttestation {
+    pub asset_symbol: String,
+    pub price: u64,
+    pub timestamp: i64,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+// Checks that the instruction is an Ed25519 program instruction verifying a single signature
+// of the vendor over the message. The signature itself is verified by the Ed25519 program,
+// the transaction fails before reaching this program if it is invalid.
+pub fn check_ed25519_instruction(
+    instruction: &Instruction,
+    vendor: &Pubkey,
+    message: &[u8],
+) -> Result<()> {
+    require!(
+        instruction.program_id == ed25519_program::ID && instruction.accounts.is_empty(),
+        DummyOracleError::InvalidAttestation
+    );
+
+    let data = &instruction.data;
+    require!(
+        data.len() >= ED25519_SIGNATURE_OFFSETS_START + ED25519_SIGNATURE_OFFSETS_SIZE
+            && data[0] == 1,
+        DummyOracleError::InvalidAttestation
+    );
+
+    // offsets of the single signature, the length is checked above
+    let offset = |i: usize| read_u16(data, ED25519_SIGNATURE_OFFSETS_START + 2 * i).unwrap();
+    let signature_instruction_index = offset(1);
+    let public_key_offset = offset(2);
+    let public_key_instruction_index = offset(3);
+    let message_data_offset = offset(4);
+    let message_data_size = offset(5);
+    let message_instruction_index = offset(6);
+
+    // signature, public key and message have to be part of the Ed25519 instruction
+    require!(
+        signature_instruction_index == ED25519_CURRENT_INSTRUCTION
+            && public_key_instruction_index == ED25519_CURRENT_INSTRUCTION
+            && message_instruction_index == ED25519_CURRENT_INSTRUCTION,
+        DummyOracleError::InvalidAttestation
+    );
+
+    let public_key_offset = public_key_offset as usize;
+    let signed_public_key = data
+        .get(public_key_offset..public_key_offset + ED25519_PUBKEY_SIZE)
+        .ok_or(DummyOracleError::InvalidAttestation)?;
+    require!(
+        signed_public_key == vendor.as_ref(),
+        DummyOracleError::InvalidVendor
+    );
+
+    let message_data_offset = message_data_offset as usize;
+    let signed_message = data
+        .get(message_data_offset..message_data_offset + message_data_size as usize)
+        .ok_or(DummyOracleError::InvalidAttestation)?;
+    require!(
+        signed_message == message,
+        DummyOracleError::InvalidAttestation
+    );
+
+    Ok(())
+}
+
+#[error_code]
+pub enum DummyOracleError {
+    #[msg("No price recorded at or before the timestamp")]
+    PriceNotFound,
+    #[msg("Invalid averaging window")]
+    InvalidWindow,
+    #[msg("No vendor set")]
+    VendorNotSet,
+    #[msg("Attestation not signed by the vendor")]
+    InvalidVendor,
+    #[msg("Invalid price attestation")]
+    InvalidAttestation,
+    #[msg("Attestation older than the current price")]
+    StaleAttestation,
+}
+
 #[program]
 pub mod dummy_oracle {
     use super::*;
@@ -11,15 +113,25 @@ pub mod dummy_oracle {
         ctx: Context<Initialize>,
         asset_symbol: String,
         initial_price: u64,
+        exponent: i32,
     ) -> Result<()> {
         let dummy_oracle = &mut ctx.accounts.dummy_oracle;
         dummy_oracle.authority = *ctx.accounts.authority.key;
         dummy_oracle.asset_symbol = asset_symbol;
         dummy_oracle.current_price = initial_price;
+        dummy_oracle.exponent = exponent;
         dummy_oracle.quote_currency_mint = ctx.accounts.quote_currency_mint.key();
         dummy_oracle.last_update = Clock::get()?.unix_timestamp;
+        dummy_oracle.vendor = None;
         dummy_oracle.bump = ctx.bumps.dummy_oracle;
 
+        let price_history = &mut ctx.accounts.price_history;
+        price_history.dummy_oracle = dummy_oracle.key();
+        price_history.next_index = 0;
+        price_history.entries = Vec::with_capacity(PRICE_HISTORY_CAPACITY);
+        price_history.bump = ctx.bumps.price_history;
+        price_history.record(dummy_oracle.last_update, initial_price);
+
         Ok(())
     }
 
@@ -32,8 +144,102 @@ pub mod dummy_oracle {
         dummy_oracle.current_price = new_price;
         dummy_oracle.last_update = Clock::get()?.unix_timestamp;
 
+        ctx.accounts
+            .price_history
+            .record(dummy_oracle.last_update, new_price);
+
+        Ok(())
+    }
+
+    pub fn set_vendor(
+        ctx: Context<SetVendor>,
+        _asset_symbol: String,
+        vendor: Option<Pubkey>,
+    ) -> Result<()> {
+        ctx.accounts.dummy_oracle.vendor = vendor;
+
+        Ok(())
+    }
+
+    // Records a price signed off-chain by the vendor. The transaction has to contain
+    // the Ed25519 program instruction verifying the signature right before this one.
+    pub fn submit_attested_price(
+        ctx: Context<SubmitAttestedPrice>,
+        asset_symbol: String,
+        price: u64,
+        timestamp: i64,
+    ) -> Result<()> {
+        let dummy_oracle = &mut ctx.accounts.dummy_oracle;
+        let vendor = dummy_oracle.vendor.ok_or(DummyOracleError::VendorNotSet)?;
+
+        require!(
+            timestamp > dummy_oracle.last_update,
+            DummyOracleError::StaleAttestation
+        );
+        require!(
+            timestamp <= Clock::get()?.unix_timestamp,
+            DummyOracleError::InvalidAttestation
+        );
+
+        let instructions = ctx.accounts.instructions.to_account_info();
+        let current_index = load_current_index_checked(&instructions)?;
+        require!(current_index > 0, DummyOracleError::InvalidAttestation);
+        let ed25519_instruction =
+            load_instruction_at_checked(current_index as usize - 1, &instructions)?;
+
+        let message = PriceAttestation {
+            asset_symbol,
+            price,
+            timestamp,
+        }
+        .try_to_vec()?;
+        check_ed25519_instruction(&ed25519_instruction, &vendor, &message)?;
+
+        msg!("Attested price {} at {}", price, timestamp);
+
+        dummy_oracle.current_price = price;
+        dummy_oracle.last_update = timestamp;
+        ctx.accounts.price_history.record(timestamp, price);
+
         Ok(())
     }
+
+    // Returns the price that was on-chain at the timestamp
+    pub fn get_price_at(ctx: Context<GetPrice>, timestamp: i64) -> Result<u64> {
+        let price = ctx
+            .accounts
+            .price_history
+            .price_at(timestamp)
+            .ok_or(DummyOracleError::PriceNotFound)?;
+        msg!("Price at {}: {}", timestamp, price);
+
+        Ok(price)
+    }
+
+    // Returns the time-weighted average price between the timestamps
+    pub fn get_twap(
+        ctx: Context<GetPrice>,
+        start_timestamp: i64,
+        end_timestamp: i64,
+    ) -> Result<u64> {
+        require!(
+            start_timestamp <= end_timestamp,
+            DummyOracleError::InvalidWindow
+        );
+        let twap = ctx
+            .accounts
+            .price_history
+            .twap(start_timestamp, end_timestamp)
+            .ok_or(DummyOracleError::PriceNotFound)?;
+        msg!(
+            "TWAP between {} and {}: {}",
+            start_timestamp,
+            end_timestamp,
+            twap
+        );
+
+        Ok(twap)
+    }
 }
 
 #[derive(Accounts)]
@@ -46,6 +252,10 @@ pub struct Initialize<'info> {
     seeds=[authority.key().as_ref(), asset_symbol.as_bytes()], bump,
     payer=authority, space=DummyOracleAccount::space(asset_symbol.len()))]
     pub dummy_oracle: Account<'info, DummyOracleAccount>,
+    #[account(init,
+    seeds=[b"history", dummy_oracle.key().as_ref()], bump,
+    payer=authority, space=PriceHistory::space())]
+    pub price_history: Box<Account<'info, PriceHistory>>,
     pub system_program: Program<'info, System>,
 }
 
@@ -55,20 +265,337 @@ pub struct UpdatePrice<'info> {
     pub authority: Signer<'info>,
     #[account(mut, seeds=[authority.key().as_ref(), asset_symbol.as_bytes()], bump=dummy_oracle.bump)]
     pub dummy_oracle: Account<'info, DummyOracleAccount>,
+    #[account(mut, seeds=[b"history", dummy_oracle.key().as_ref()], bump=price_history.bump)]
+    pub price_history: Box<Account<'info, PriceHistory>>,
+}
+
+#[derive(Accounts)]
+#[instruction(asset_symbol: String)]
+pub struct SetVendor<'info> {
+    pub authority: Signer<'info>,
+    #[account(mut, seeds=[authority.key().as_ref(), asset_symbol.as_bytes()], bump=dummy_oracle.bump)]
+    pub dummy_oracle: Account<'info, DummyOracleAccount>,
+}
+
+#[derive(Accounts)]
+#[instruction(asset_symbol: String)]
+pub struct SubmitAttestedPrice<'info> {
+    pub payer: Signer<'info>,
+    #[account(mut, seeds=[dummy_oracle.authority.as_ref(), asset_symbol.as_bytes()], bump=dummy_oracle.bump)]
+    pub dummy_oracle: Account<'info, DummyOracleAccount>,
+    #[account(mut, seeds=[b"history", dummy_oracle.key().as_ref()], bump=price_history.bump)]
+    pub price_history: Box<Account<'info, PriceHistory>>,
+    /// CHECK: instructions sysvar
+    #[account(address=anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct GetPrice<'info> {
+    pub dummy_oracle: Account<'info, DummyOracleAccount>,
+    #[account(seeds=[b"history", dummy_oracle.key().as_ref()], bump=price_history.bump)]
+    pub price_history: Box<Account<'info, PriceHistory>>,
 }
 
+// The price is current_price * 10^exponent units of the quote currency
 #[account]
 pub struct DummyOracleAccount {
     pub authority: Pubkey,
     pub asset_symbol: String,
     pub current_price: u64,
+    pub exponent: i32,
     pub quote_currency_mint: Pubkey,
     pub last_update: i64,
+    pub vendor: Option<Pubkey>,
     pub bump: u8,
 }
 
 impl DummyOracleAccount {
     pub fn space(asset_symbol_len_in_bytes: usize) -> usize {
-        8 + 32 + 4 + asset_symbol_len_in_bytes + 8 + 32 + 8 + 1
+        8 + 32 + 4 + asset_symbol_len_in_bytes + 8 + 4 + 32 + 8 + 33 + 1
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PriceEntry {
+    pub timestamp: i64,
+    pub price: u64,
+}
+
+// Ring buffer of the last PRICE_HISTORY_CAPACITY prices, next_index points at the
+// oldest entry once the buffer is full.
+#[account]
+pub struct PriceHistory {
+    pub dummy_oracle: Pubkey,
+    pub next_index: u16,
+    pub entries: Vec<PriceEntry>,
+    pub bump: u8,
+}
+
+impl PriceHistory {
+    pub fn space() -> usize {
+        8 + 32 + 2 + 4 + 16 * PRICE_HISTORY_CAPACITY + 1
+    }
+
+    pub fn record(&mut self, timestamp: i64, price: u64) {
+        // several updates within the same second, only the last one was observable
+        if let Some(last) = self.chronological().last() {
+            if last.timestamp == timestamp {
+                let last_index = (self.next_index as usize + PRICE_HISTORY_CAPACITY - 1)
+                    % PRICE_HISTORY_CAPACITY;
+                self.entries[last_index].price = price;
+                return;
+            }
+        }
+
+        let entry = PriceEntry { timestamp, price };
+        if self.entries.len() < PRICE_HISTORY_CAPACITY {
+            self.entries.push(entry);
+        } else {
+            self.entries[self.next_index as usize] = entry;
+        }
+        self.next_index = ((self.next_index as usize + 1) % PRICE_HISTORY_CAPACITY) as u16;
+    }
+
+    // Entries from oldest to newest
+    pub fn chronological(&self) -> Vec<PriceEntry> {
+        if self.entries.len() < PRICE_HISTORY_CAPACITY {
+            return self.entries.clone();
+        }
+        let (newest, oldest) = self.entries.split_at(self.next_index as usize);
+        oldest.iter().chain(newest.iter()).copied().collect()
+    }
+
+    // Latest price recorded at or before the timestamp, None if the timestamp
+    // is before the oldest retained entry
+    pub fn price_at(&self, timestamp: i64) -> Option<u64> {
+        self.chronological()
+            .iter()
+            .rev()
+            .find(|entry| entry.timestamp <= timestamp)
+            .map(|entry| entry.price)
+    }
+
+    // Each price is weighted by the time it was in effect within [start_timestamp, end_timestamp],
+    // an empty window returns the price at start_timestamp
+    pub fn twap(&self, start_timestamp: i64, end_timestamp: i64) -> Option<u64> {
+        let mut price = self.price_at(start_timestamp)?;
+        if start_timestamp == end_timestamp {
+            return Some(price);
+        }
+
+        let mut weighted_sum: u128 = 0;
+        let mut from = start_timestamp;
+        for entry in self
+            .chronological()
+            .iter()
+            .filter(|entry| entry.timestamp > start_timestamp && entry.timestamp < end_timestamp)
+        {
+            weighted_sum += price as u128 * (entry.timestamp - from) as u128;
+            from = entry.timestamp;
+            price = entry.price;
+        }
+        weighted_sum += price as u128 * (end_timestamp - from) as u128;
+
+        Some((weighted_sum / (end_timestamp - start_timestamp) as u128) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signature, Signer};
+
+    fn price_history(entries: &[(i64, u64)]) -> PriceHistory {
+        let mut price_history = PriceHistory {
+            dummy_oracle: Pubkey::new_unique(),
+            next_index: 0,
+            entries: Vec::new(),
+            bump: 255,
+        };
+        for (timestamp, price) in entries {
+            price_history.record(*timestamp, *price);
+        }
+        price_history
+    }
+
+    macro_rules! price_at_tests {
+        ($($name:ident: $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (entries, timestamp, expected) = $expected;
+                    let result = price_history(entries).price_at(timestamp);
+                    assert_eq!(result, expected);
+                }
+            )*
+        }
+    }
+
+    #[cfg(test)]
+    price_at_tests! {
+        price_at_test_1: (&[(100, 42000), (200, 43000), (300, 41000)], 200, Some(43000),),
+        price_at_test_2: (&[(100, 42000), (200, 43000), (300, 41000)], 299, Some(43000),),
+        price_at_test_3: (&[(100, 42000), (200, 43000), (300, 41000)], 1000, Some(41000),),
+        price_at_test_4: (&[(100, 42000), (200, 43000), (300, 41000)], 99, None,),
+        price_at_test_5: (&[(100, 42000), (100, 43000), (300, 41000)], 100, Some(43000),),
+        price_at_test_6: (&[], 100, None,),
+    }
+
+    macro_rules! twap_tests {
+        ($($name:ident: $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (entries, start_timestamp, end_timestamp, expected) = $expected;
+                    let result = price_history(entries).twap(start_timestamp, end_timestamp);
+                    assert_eq!(result, expected);
+                }
+            )*
+        }
+    }
+
+    #[cfg(test)]
+    twap_tests! {
+        twap_test_1: (&[(100, 42000), (200, 43000), (300, 41000)], 100, 300, Some(42500),),
+        twap_test_2: (&[(100, 42000), (200, 43000), (300, 41000)], 100, 400, Some(42000),),
+        twap_test_3: (&[(100, 42000), (200, 43000), (300, 41000)], 150, 250, Some(42500),),
+        twap_test_4: (&[(100, 42000), (200, 43000), (300, 41000)], 200, 200, Some(43000),),
+        twap_test_5: (&[(100, 42000), (200, 43000), (300, 41000)], 50, 250, None,),
+        twap_test_6: (&[(100, 42000), (400, 45000)], 100, 500, Some(42750),),
+    }
+
+    fn ed25519_instruction(keypair: &Keypair, message: &[u8]) -> Instruction {
+        let signature = keypair.sign(message).to_bytes();
+        let public_key_offset = ED25519_SIGNATURE_OFFSETS_START + ED25519_SIGNATURE_OFFSETS_SIZE;
+        let signature_offset = public_key_offset + ED25519_PUBKEY_SIZE;
+        let message_data_offset = signature_offset + signature.len();
+
+        let mut data = vec![1u8, 0];
+        for offset in [
+            signature_offset as u16,
+            ED25519_CURRENT_INSTRUCTION,
+            public_key_offset as u16,
+            ED25519_CURRENT_INSTRUCTION,
+            message_data_offset as u16,
+            message.len() as u16,
+            ED25519_CURRENT_INSTRUCTION,
+        ] {
+            data.extend_from_slice(&offset.to_le_bytes());
+        }
+        data.extend_from_slice(keypair.public.as_bytes());
+        data.extend_from_slice(&signature);
+        data.extend_from_slice(message);
+
+        Instruction {
+            program_id: ed25519_program::ID,
+            accounts: vec![],
+            data,
+        }
+    }
+
+    fn vendor_keypair(seed: u8) -> Keypair {
+        let secret = SecretKey::from_bytes(&[seed; 32]).unwrap();
+        let public = PublicKey::from(&secret);
+        Keypair { secret, public }
+    }
+
+    fn attestation_message(price: u64) -> Vec<u8> {
+        PriceAttestation {
+            asset_symbol: "BTC".to_string(),
+            price,
+            timestamp: 1700000000,
+        }
+        .try_to_vec()
+        .unwrap()
+    }
+
+    #[test]
+    fn check_ed25519_instruction_accepts_vendor_signature() {
+        let vendor = vendor_keypair(1);
+        let message = attestation_message(42000);
+        let instruction = ed25519_instruction(&vendor, &message);
+
+        let data = &instruction.data;
+        let signature = Signature::from_bytes(&data[48..112]).unwrap();
+        assert!(vendor.verify(&data[112..], &signature).is_ok());
+
+        let vendor_key = Pubkey::new_from_array(vendor.public.to_bytes());
+        assert!(check_ed25519_instruction(&instruction, &vendor_key, &message).is_ok());
+    }
+
+    #[test]
+    fn check_ed25519_instruction_rejects_other_signer() {
+        let vendor = vendor_keypair(1);
+        let message = attestation_message(42000);
+        let instruction = ed25519_instruction(&vendor_keypair(2), &message);
+
+        let vendor_key = Pubkey::new_from_array(vendor.public.to_bytes());
+        assert_eq!(
+            check_ed25519_instruction(&instruction, &vendor_key, &message),
+            Err(DummyOracleError::InvalidVendor.into())
+        );
+    }
+
+    #[test]
+    fn check_ed25519_instruction_rejects_other_message() {
+        let vendor = vendor_keypair(1);
+        let instruction = ed25519_instruction(&vendor, &attestation_message(42000));
+
+        let vendor_key = Pubkey::new_from_array(vendor.public.to_bytes());
+        assert_eq!(
+            check_ed25519_instruction(&instruction, &vendor_key, &attestation_message(43000)),
+            Err(DummyOracleError::InvalidAttestation.into())
+        );
+    }
+
+    #[test]
+    fn check_ed25519_instruction_rejects_other_program() {
+        let vendor = vendor_keypair(1);
+        let message = attestation_message(42000);
+        let mut instruction = ed25519_instruction(&vendor, &message);
+        instruction.program_id = Pubkey::new_unique();
+
+        let vendor_key = Pubkey::new_from_array(vendor.public.to_bytes());
+        assert_eq!(
+            check_ed25519_instruction(&instruction, &vendor_key, &message),
+            Err(DummyOracleError::InvalidAttestation.into())
+        );
+    }
+
+    #[test]
+    fn check_ed25519_instruction_rejects_data_from_other_instruction() {
+        let vendor = vendor_keypair(1);
+        let message = attestation_message(42000);
+        let mut instruction = ed25519_instruction(&vendor, &message);
+        // message instruction index
+        instruction.data[14..16].copy_from_slice(&0u16.to_le_bytes());
+
+        let vendor_key = Pubkey::new_from_array(vendor.public.to_bytes());
+        assert_eq!(
+            check_ed25519_instruction(&instruction, &vendor_key, &message),
+            Err(DummyOracleError::InvalidAttestation.into())
+        );
+    }
+
+    #[test]
+    fn record_overwrites_oldest_entry_when_full() {
+        let entries: Vec<(i64, u64)> = (0..PRICE_HISTORY_CAPACITY as i64 + 10)
+            .map(|i| (i * 10, i as u64))
+            .collect();
+        let price_history = price_history(&entries);
+
+        assert_eq!(price_history.entries.len(), PRICE_HISTORY_CAPACITY);
+        let chronological = price_history.chronological();
+        assert_eq!(chronological.first().unwrap().timestamp, 100);
+        assert_eq!(
+            chronological.last().unwrap().timestamp,
+            (PRICE_HISTORY_CAPACITY as i64 + 9) * 10
+        );
+        assert!(chronological
+            .windows(2)
+            .all(|w| w[0].timestamp < w[1].timestamp));
+        assert_eq!(price_history.price_at(99), None);
+        assert_eq!(price_history.price_at(105), Some(10));
     }
 }
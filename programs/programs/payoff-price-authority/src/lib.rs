@@ -0,0 +1,315 @@
+use anchor_lang::prelude::*;
+use brc_price_authority::{rescale, validate_fixing_timestamp, OracleSource, Rounding};
+use payoff::{Node, Payoff, PayoffError};
+use structured_product::program::StructuredProduct;
+use structured_product::{Payment, StructuredProductConfig};
+
+declare_id!("3ed94xCh61aj2rVJLguLnVppemFq3nfx1gTNVYr286p8");
+
+pub const MAX_UNDERLYINGS: usize = 5;
+
+// Oracle prices are given as (price, exponent) and rescaled into 10^price_exponent units,
+// rounded down, before evaluating the payoff. Returns the fixings and the payment per unit.
+pub fn calc_payment_per_unit(
+    payoff: &Payoff,
+    oracle_prices: &[(u64, i32)],
+    price_exponent: i32,
+) -> Result<(Vec<u64>, u64)> {
+    let fixings = oracle_prices
+        .iter()
+        .map(|&(price, exponent)| rescale(price, exponent, price_exponent, Rounding::Down))
+        .collect::<Option<Vec<u64>>>()
+        .ok_or(PayoffPriceAuthorityError::Overflow)?;
+
+    let payment_per_unit = payoff.evaluate(&fixings).map_err(|error: PayoffError| {
+        msg!("Payoff evaluation failed: {:?}", error);
+        PayoffPriceAuthorityError::PayoffEvaluationFailed
+    })?;
+
+    Ok((fixings, payment_per_unit))
+}
+
+#[error_code]
+pub enum PayoffPriceAuthorityError {
+    #[msg("Unauthorized")]
+    Unauthorized,
+    #[msg("Invalid underlyings")]
+    InvalidUnderlyings,
+    #[msg("Invalid oracle")]
+    InvalidOracle,
+    #[msg("Invalid payoff expression")]
+    InvalidPayoff,
+    #[msg("Payoff evaluation failed")]
+    PayoffEvaluationFailed,
+    #[msg("Payment already fixed")]
+    AlreadyFixed,
+    #[msg("Product not issued")]
+    NotIssued,
+    #[msg("Amount overflow")]
+    Overflow,
+}
+
+#[program]
+pub mod payoff_price_authority {
+    use super::*;
+    use structured_product::cpi::accounts::SetPaymentPrice;
+    use structured_product::cpi::set_payment_price;
+
+    // The oracle of every underlying has to be passed as remaining accounts, in order.
+    // Fixing i of the payoff expression is the price of the i-th underlying in 10^price_exponent units.
+    #[allow(clippy::too_many_arguments)]
+    pub fn initialize<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Initialize<'info>>,
+        _payment_date_offset: i64,
+        _principal: bool,
+        underlying_symbols: Vec<String>,
+        nodes: Vec<Node>,
+        price_exponent: i32,
+        max_staleness: i64,
+        fixing_window: i64,
+        oracle_source: OracleSource,
+    ) -> Result<()> {
+        require!(
+            underlying_symbols.len() <= MAX_UNDERLYINGS
+                && ctx.remaining_accounts.len() == underlying_symbols.len(),
+            PayoffPriceAuthorityError::InvalidUnderlyings
+        );
+
+        let payoff = Payoff { nodes };
+        payoff
+            .validate(underlying_symbols.len())
+            .map_err(|_| PayoffPriceAuthorityError::InvalidPayoff)?;
+
+        let mut oracles = Vec::with_capacity(underlying_symbols.len());
+        for (underlying_symbol, oracle_info) in
+            underlying_symbols.iter().zip(ctx.remaining_accounts)
+        {
+            oracle_source.validate_oracle(
+                oracle_info,
+                ctx.accounts.authority.key,
+                underlying_symbol,
+            )?;
+            oracles.push(oracle_info.key());
+        }
+
+        msg!(
+            "Initializing payoff price authority at {}",
+            ctx.accounts.payoff_authority.key()
+        );
+
+        let payoff_authority = &mut ctx.accounts.payoff_authority;
+
+        payoff_authority.authority = *ctx.accounts.authority.key;
+        payoff_authority.structured_product = ctx.accounts.structured_product.key();
+        payoff_authority.target_payment = ctx.accounts.payment.key();
+        payoff_authority.oracles = oracles;
+        payoff_authority.payoff = payoff;
+        payoff_authority.price_exponent = price_exponent;
+        payoff_authority.oracle_source = oracle_source;
+        payoff_authority.max_staleness = max_staleness;
+        payoff_authority.fixing_window = fixing_window;
+        // to be set by the fixing
+        payoff_authority.fixings = Vec::new();
+        payoff_authority.payment_per_unit = None;
+        payoff_authority.fixing_date = None;
+
+        payoff_authority.bump = ctx.bumps.payoff_authority;
+        msg!("Payoff authority: {:?}", payoff_authority);
+
+        Ok(())
+    }
+
+    // Permissionless, the oracle of every underlying has to be passed as remaining accounts, in order.
+    // Every oracle price has to be published within the fixing window of the payment date.
+    pub fn set_fixing<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SetFixing<'info>>,
+        payment_date_offset: i64,
+    ) -> Result<()> {
+        let payoff_authority = &ctx.accounts.payoff_authority;
+        require!(
+            payoff_authority.payment_per_unit.is_none(),
+            PayoffPriceAuthorityError::AlreadyFixed
+        );
+        require!(
+            ctx.remaining_accounts.len() == payoff_authority.oracles.len(),
+            PayoffPriceAuthorityError::InvalidUnderlyings
+        );
+
+        // payment dates are offsets to the issuance date
+        let fixing_date = ctx
+            .accounts
+            .structured_product
+            .issuance_date()
+            .ok_or(PayoffPriceAuthorityError::NotIssued)?
+            + payment_date_offset;
+        let current_time = Clock::get()?.unix_timestamp;
+
+        let mut oracle_prices = Vec::with_capacity(payoff_authority.oracles.len());
+        for (oracle, oracle_info) in payoff_authority.oracles.iter().zip(ctx.remaining_accounts) {
+            require!(
+                oracle_info.key() == *oracle,
+                PayoffPriceAuthorityError::InvalidOracle
+            );
+            let oracle_price = payoff_authority.oracle_source.read_price(oracle_info)?;
+            validate_fixing_timestamp(
+                oracle_price.publish_time,
+                fixing_date,
+                payoff_authority.fixing_window,
+                payoff_authority.max_staleness,
+                current_time,
+            )?;
+            oracle_prices.push((oracle_price.price as u64, oracle_price.exponent));
+        }
+
+        // fixings are evaluated in the scale of the payoff expression
+        let (fixings, payment_per_unit) = calc_payment_per_unit(
+            &payoff_authority.payoff,
+            &oracle_prices,
+            payoff_authority.price_exponent,
+        )?;
+
+        msg!(
+            "Fixings: {:?}, payment per unit: {}",
+            fixings,
+            payment_per_unit
+        );
+
+        let cpi_program = ctx.accounts.structured_product_program.to_account_info();
+
+        let cpi_accounts = SetPaymentPrice {
+            authority: ctx.accounts.payoff_authority.to_account_info(),
+            payment: ctx.accounts.payment.to_account_info(),
+            structured_product: ctx.accounts.structured_product.to_account_info(),
+        };
+
+        let payment_key = ctx.accounts.payment.key();
+
+        let seeds = &[payment_key.as_ref(), &[ctx.accounts.payoff_authority.bump]];
+
+        set_payment_price(
+            CpiContext::new_with_signer(cpi_program, cpi_accounts, &[&seeds[..]]),
+            payment_date_offset,
+            payment_per_unit,
+        )?;
+
+        let payoff_authority = &mut ctx.accounts.payoff_authority;
+
+        payoff_authority.fixings = fixings;
+        payoff_authority.payment_per_unit = Some(payment_per_unit);
+        payoff_authority.fixing_date = Some(Clock::get()?.unix_timestamp);
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(payment_date_offset: i64, principal: bool, underlying_symbols: Vec<String>, nodes: Vec<Node>)]
+pub struct Initialize<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account()]
+    pub structured_product: Account<'info, StructuredProductConfig>,
+    /// CHECK:
+    #[account(seeds=[structured_product.key().as_ref(), &[principal.into()], &payment_date_offset.to_le_bytes()],
+    bump,
+    seeds::program=structured_product_program)]
+    pub payment: AccountInfo<'info>,
+    #[account(init,
+    seeds=[payment.key().as_ref()],
+    bump,
+    payer=authority,
+    space=PayoffAuthority::space(underlying_symbols.len(), nodes.len()))]
+    pub payoff_authority: Account<'info, PayoffAuthority>,
+    pub structured_product_program: Program<'info, StructuredProduct>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetFixing<'info> {
+    pub payer: Signer<'info>,
+    #[account(mut,
+    seeds=[payment.key().as_ref()],
+    bump=payoff_authority.bump,
+    has_one=structured_product @ PayoffPriceAuthorityError::Unauthorized)]
+    pub payoff_authority: Account<'info, PayoffAuthority>,
+    pub structured_product: Account<'info, StructuredProductConfig>,
+    #[account(mut)]
+    pub payment: Account<'info, Payment>,
+    pub structured_product_program: Program<'info, StructuredProduct>,
+}
+
+#[account]
+#[derive(Debug)]
+pub struct PayoffAuthority {
+    pub authority: Pubkey,
+    pub structured_product: Pubkey,
+    pub target_payment: Pubkey,
+    pub oracles: Vec<Pubkey>,
+    pub payoff: Payoff,
+    pub price_exponent: i32,
+    pub oracle_source: OracleSource,
+    pub max_staleness: i64,
+    pub fixing_window: i64,
+    pub fixings: Vec<u64>,
+    pub payment_per_unit: Option<u64>,
+    pub fixing_date: Option<i64>,
+    pub bump: u8,
+}
+
+impl PayoffAuthority {
+    pub fn space(num_underlyings: usize, num_nodes: usize) -> usize {
+        8 + 32
+            + 32
+            + 32
+            + 4
+            + 32 * num_underlyings
+            + Payoff::space(num_nodes)
+            + 4
+            + 1
+            + 8
+            + 8
+            + 4
+            + 8 * num_underlyings
+            + 9
+            + 9
+            + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! calc_payment_per_unit_tests {
+        ($($name:ident: $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (nodes, oracle_prices, price_exponent, expected) = $expected;
+                    let payoff = Payoff { nodes: nodes.to_vec() };
+                    let result = calc_payment_per_unit(&payoff, &oracle_prices, price_exponent);
+                    assert_eq!(result.ok(), expected);
+                }
+            )*
+        }
+    }
+
+    #[cfg(test)]
+    calc_payment_per_unit_tests! {
+        // oracle already in the payoff scale
+        calc_payment_per_unit_test_1: ([Node::Fixing(0)], [(42000, 0)], 0, Some((vec![42000], 42000)),),
+        // pyth style 10^-8 prices rescaled to whole units, rounded down
+        calc_payment_per_unit_test_2: ([Node::Fixing(0)], [(4200099999999, -8)], 0, Some((vec![42000], 42000)),),
+        // scaled up into a finer exponent
+        calc_payment_per_unit_test_3: ([Node::Fixing(0)], [(42000, 0)], -2, Some((vec![4200000], 4200000)),),
+        // underlyings with different oracle exponents compared in the same scale
+        calc_payment_per_unit_test_4: ([Node::Fixing(0), Node::Fixing(1), Node::Min(0, 1)], [(4200000000000, -8), (41000000, -3)], 0, Some((vec![42000, 41000], 41000)),),
+        // 100000 * final / initial with the initial fixing given in 10^-2 units
+        calc_payment_per_unit_test_5: ([Node::Const(100000), Node::Fixing(0), Node::Const(4200000), Node::MulDiv(0, 1, 2)], [(3000000000000, -8)], -2, Some((vec![3000000], 71428)),),
+        // rescaling overflow
+        calc_payment_per_unit_test_6: ([Node::Fixing(0)], [(u64::MAX, 0)], -2, None,),
+        // evaluation failure
+        calc_payment_per_unit_test_7: ([Node::Fixing(0), Node::Const(50000), Node::Sub(0, 1)], [(42000, 0)], 0, None,),
+        // missing fixing
+        calc_payment_per_unit_test_8: ([Node::Fixing(1)], [(42000, 0)], 0, None,),
+    }
+}
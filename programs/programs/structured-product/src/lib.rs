@@ -2,7 +2,9 @@ use anchor_lang::prelude::*;
 use anchor_spl::associated_token::AssociatedToken;
 use anchor_spl::metadata::Metadata;
 use anchor_spl::token_2022::Token2022;
-use anchor_spl::token_interface::{Mint, TokenAccount};
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+use solana_program::instruction::Instruction;
+use spl_token_2022::check_spl_token_program_account;
 use spl_token_2022::instruction::TokenInstruction;
 
 use transfer_snapshot_hook::program::TransferSnapshotHook;
@@ -38,6 +40,118 @@ pub enum StructuredProductError {
     PrincipalUndefined,
     #[msg("Insufficient balance")]
     InsufficientBalance,
+    #[msg("Invalid amount")]
+    InvalidAmount,
+    #[msg("Subscriptions closed")]
+    SubscriptionsClosed,
+    #[msg("Subscriptions not closed")]
+    SubscriptionsNotClosed,
+    #[msg("No subscriptions")]
+    NoSubscriptions,
+    #[msg("Subscription book not fully issued")]
+    BookNotIssued,
+    #[msg("Subscription book in use")]
+    SubscriptionBookInUse,
+    #[msg("Offer cancelled")]
+    Cancelled,
+    #[msg("Offer not cancelled")]
+    NotCancelled,
+    #[msg("Offer not expired")]
+    OfferNotExpired,
+    #[msg("Offer expired")]
+    OfferExpired,
+    #[msg("Legal approval missing")]
+    LegalApprovalMissing,
+    #[msg("Not issued")]
+    NotIssued,
+    #[msg("Already called")]
+    AlreadyCalled,
+    #[msg("Payment cancelled")]
+    PaymentCancelled,
+    #[msg("Physical delivery not enabled")]
+    PhysicalDeliveryNotEnabled,
+    #[msg("Invalid delivery accounts")]
+    InvalidDeliveryAccounts,
+    #[msg("Fixing not final")]
+    FixingNotFinal,
+    #[msg("Dispute window closed")]
+    DisputeWindowClosed,
+    #[msg("Fixing not disputable")]
+    NotDisputable,
+    #[msg("Invalid dispute settings")]
+    InvalidDisputeSettings,
+    #[msg("Invalid reservation accounts")]
+    InvalidReservationAccounts,
+}
+
+// Units allocated to a subscription once the book is closed.
+// If the book is oversubscribed every subscription is scaled down pro rata, rounding down.
+// Units lost to rounding are simply never minted.
+pub fn calc_allocation(units: u64, total_subscribed: u64, supply: u64) -> u64 {
+    if total_subscribed <= supply {
+        return units;
+    }
+    ((units as u128 * supply as u128) / total_subscribed as u128) as u64
+}
+
+// Transfers note tokens with the snapshot hook accounts appended, so the hook records the
+// balances of source and destination.
+// TODO: This could probably be done with less manual work using spl transfer_checked and tlv account resolution
+#[allow(clippy::too_many_arguments)]
+fn transfer_with_snapshot_hook<'info>(
+    token_program: &AccountInfo<'info>,
+    source: &AccountInfo<'info>,
+    mint: &AccountInfo<'info>,
+    destination: &AccountInfo<'info>,
+    authority: &AccountInfo<'info>,
+    extra_account_meta_list: &AccountInfo<'info>,
+    snapshot_config: &AccountInfo<'info>,
+    source_snapshot_balances: &AccountInfo<'info>,
+    destination_snapshot_balances: &AccountInfo<'info>,
+    snapshot_transfer_hook_program: &AccountInfo<'info>,
+    amount: u64,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    msg!("Building transfer_checked ix!");
+    check_spl_token_program_account(&token_program.key())?;
+    let data = TokenInstruction::TransferChecked {
+        amount,
+        decimals: 0,
+    }
+    .pack();
+
+    let accounts = vec![
+        AccountMeta::new(source.key(), false),
+        AccountMeta::new_readonly(mint.key(), false),
+        AccountMeta::new(destination.key(), false),
+        AccountMeta::new_readonly(authority.key(), true),
+        AccountMeta::new_readonly(extra_account_meta_list.key(), false),
+        AccountMeta::new_readonly(snapshot_config.key(), false),
+        AccountMeta::new(source_snapshot_balances.key(), false),
+        AccountMeta::new(destination_snapshot_balances.key(), false),
+        AccountMeta::new_readonly(snapshot_transfer_hook_program.key(), false),
+    ];
+    let cpi_account_infos = vec![
+        source.clone(),
+        mint.clone(),
+        destination.clone(),
+        authority.clone(),
+        extra_account_meta_list.clone(),
+        snapshot_config.clone(),
+        source_snapshot_balances.clone(),
+        destination_snapshot_balances.clone(),
+        snapshot_transfer_hook_program.clone(),
+    ];
+
+    let transfer_checked_ix = Instruction {
+        program_id: token_program.key(),
+        accounts,
+        data,
+    };
+
+    msg!("Invoke transfer_checked ix!");
+    solana_program::program::invoke_signed(&transfer_checked_ix, &cpi_account_infos, signer_seeds)?;
+    Ok(())
 }
 
 #[program]
@@ -47,10 +161,9 @@ pub mod structured_product {
     };
     use anchor_spl::metadata::mpl_token_metadata::types::TokenStandard::Fungible;
     use anchor_spl::token_2022;
-    use solana_program::instruction::Instruction;
-    use spl_token_2022::check_spl_token_program_account;
+    use anchor_spl::token_interface;
 
-    use treasury_wallet::cpi::accounts::Withdraw;
+    use treasury_wallet::cpi::accounts::{Reserve, Withdraw};
 
     use super::*;
 
@@ -59,6 +172,8 @@ pub mod structured_product {
         max_snapshots: u8,
         payment_amount_per_unit: u64,
         supply: u64,
+        offer_expiry: Option<i64>,
+        legal_approver: Option<Pubkey>,
     ) -> Result<()> {
         let init_transfer_hook_instruction =
             token_2022::spl_token_2022::extension::transfer_hook::instruction::initialize(
@@ -147,6 +262,16 @@ pub mod structured_product {
         structured_product.num_payments = 0;
         structured_product.principal_defined = false;
         structured_product.issuance_date = None;
+        structured_product.subscriptions_closed = false;
+        structured_product.total_subscribed = 0;
+        structured_product.total_issued = 0;
+        structured_product.offer_expiry = offer_expiry;
+        structured_product.legal_approver = legal_approver;
+        structured_product.issuance_payer = None;
+        structured_product.cancelled = false;
+        structured_product.call_date_offset = None;
+        structured_product.dispute_window = 0;
+        structured_product.calculation_agent = None;
         structured_product.bump = ctx.bumps.structured_product;
 
         Ok(())
@@ -207,8 +332,13 @@ pub mod structured_product {
         payment.principal = principal;
         payment.price_authority = None;
         payment.price_per_unit = Some(price_per_unit);
+        payment.fixing_status = FixingStatus::Final;
         payment.payment_mint = ctx.accounts.payment_mint.key();
         payment.paid = false;
+        payment.cancelled = false;
+        payment.early_redemption_date_offset = None;
+        payment.delivery_mint = None;
+        payment.delivery_per_unit = None;
         payment.bump = ctx.bumps.payment;
 
         let structured_product = &mut ctx.accounts.structured_product;
@@ -277,8 +407,13 @@ pub mod structured_product {
         let payment = &mut ctx.accounts.payment;
         payment.principal = principal;
         payment.price_authority = Some(ctx.accounts.price_authority.key());
+        payment.fixing_status = FixingStatus::Final;
         payment.payment_mint = ctx.accounts.payment_mint.key();
         payment.paid = false;
+        payment.cancelled = false;
+        payment.early_redemption_date_offset = None;
+        payment.delivery_mint = None;
+        payment.delivery_per_unit = None;
         payment.bump = ctx.bumps.payment;
 
         let structured_product = &mut ctx.accounts.structured_product;
@@ -290,15 +425,96 @@ pub mod structured_product {
         Ok(())
     }
 
+    // The principal payment can deliver tokens of the delivery mint next to the cash payment,
+    // the delivered amount is set by the price authority with set_payment_delivery.
+    pub fn set_physical_delivery(
+        ctx: Context<SetPhysicalDelivery>,
+        _payment_date_offset: i64,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.structured_product.authority.key(),
+            StructuredProductError::Unauthorized
+        );
+        require!(
+            ctx.accounts.structured_product.issuance_date.is_none(),
+            StructuredProductError::AlreadyIssued
+        );
+        require!(
+            ctx.accounts.payment.price_authority.is_some(),
+            StructuredProductError::Unauthorized
+        );
+
+        let payment = &mut ctx.accounts.payment;
+        payment.delivery_mint = Some(ctx.accounts.delivery_mint.key());
+
+        Ok(())
+    }
+
+    pub fn set_legal_approver(
+        ctx: Context<SetLegalApprover>,
+        legal_approver: Option<Pubkey>,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.structured_product.authority.key(),
+            StructuredProductError::Unauthorized
+        );
+        require!(
+            ctx.accounts.structured_product.issuance_date.is_none(),
+            StructuredProductError::AlreadyIssued
+        );
+
+        let structured_product = &mut ctx.accounts.structured_product;
+        structured_product.legal_approver = legal_approver;
+        Ok(())
+    }
+
+    // Fixings of variable payments only become final dispute_window seconds after the price
+    // authority proposed them. Within the window the investor or issuer can dispute a fixing
+    // and the calculation agent can correct it.
+    pub fn set_fixing_dispute_settings(
+        ctx: Context<SetFixingDisputeSettings>,
+        dispute_window: i64,
+        calculation_agent: Option<Pubkey>,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.structured_product.authority.key(),
+            StructuredProductError::Unauthorized
+        );
+        require!(
+            ctx.accounts.structured_product.issuance_date.is_none(),
+            StructuredProductError::AlreadyIssued
+        );
+        require!(
+            dispute_window >= 0 && (dispute_window == 0 || calculation_agent.is_some()),
+            StructuredProductError::InvalidDisputeSettings
+        );
+
+        let structured_product = &mut ctx.accounts.structured_product;
+        structured_product.dispute_window = dispute_window;
+        structured_product.calculation_agent = calculation_agent;
+        Ok(())
+    }
+
     pub fn pay_issuance(ctx: Context<PayIssuance>) -> Result<()> {
         require!(
             !ctx.accounts.structured_product.paid,
             StructuredProductError::AlreadyPaid
         );
+        require!(
+            ctx.accounts.structured_product.total_subscribed == 0,
+            StructuredProductError::SubscriptionBookInUse
+        );
+        require!(
+            !ctx.accounts.structured_product.cancelled,
+            StructuredProductError::Cancelled
+        );
+        ctx.accounts
+            .structured_product
+            .check_offer_not_expired(Clock::get()?.unix_timestamp)?;
 
         let cpi_program = ctx.accounts.token_program.to_account_info();
 
-        let cpi_accounts = token_2022::TransferChecked {
+        let cpi_accounts = token_interface::TransferChecked {
             from: ctx.accounts.payer_token_account.to_account_info(),
             to: ctx
                 .accounts
@@ -308,7 +524,7 @@ pub mod structured_product {
             authority: ctx.accounts.payer.to_account_info(),
         };
 
-        token_2022::transfer_checked(
+        token_interface::transfer_checked(
             CpiContext::new(cpi_program, cpi_accounts),
             ctx.accounts.structured_product.supply
                 * ctx
@@ -320,6 +536,7 @@ pub mod structured_product {
 
         let structured_product = &mut ctx.accounts.structured_product;
         structured_product.paid = true;
+        structured_product.issuance_payer = Some(ctx.accounts.payer.key());
         Ok(())
     }
 
@@ -379,7 +596,12 @@ pub mod structured_product {
         Ok(())
     }
 
-    pub fn issue(ctx: Context<Issue>) -> Result<()> {
+    // Static payments passed as remaining accounts, in the order of reserved_payment_date_offsets,
+    // are reserved in the issuer treasury wallet so investors can see they are pre-funded
+    pub fn issue<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Issue<'info>>,
+        reserved_payment_date_offsets: Vec<i64>,
+    ) -> Result<()> {
         require!(
             ctx.accounts.issuer.key() == ctx.accounts.structured_product.issuer.key(),
             StructuredProductError::Unauthorized
@@ -396,12 +618,20 @@ pub mod structured_product {
             ctx.accounts.structured_product.paid,
             StructuredProductError::Unpaid
         );
+        require!(
+            !ctx.accounts.structured_product.cancelled,
+            StructuredProductError::Cancelled
+        );
+        ctx.accounts
+            .structured_product
+            .check_offer_not_expired(Clock::get()?.unix_timestamp)?;
+        ctx.accounts
+            .structured_product
+            .check_legal_approval(&ctx.accounts.legal_approver)?;
 
         let mint_key = ctx.accounts.mint.key();
         let signer_seeds = &[mint_key.as_ref(), &[ctx.accounts.structured_product.bump]];
 
-        let token_program = ctx.accounts.token_program.to_account_info();
-
         let cpi_accounts = token_2022::MintTo {
             mint: ctx.accounts.mint.to_account_info(),
             to: ctx.accounts.program_token_account.to_account_info(),
@@ -474,70 +704,100 @@ pub mod structured_product {
             &[&signer_seeds[..]],
         ))?;
 
-        // TODO: This could probably be done with less manual work using spl transfer_checked and tlv account resolution
-        msg!("Building transfer_checked ix!");
-        check_spl_token_program_account(&ctx.accounts.token_program.key())?;
-        let data = TokenInstruction::TransferChecked {
-            amount: ctx.accounts.structured_product.supply,
-            decimals: 0,
-        }
-        .pack();
-
-        let accounts = vec![
-            AccountMeta::new(ctx.accounts.program_token_account.key(), false),
-            AccountMeta::new_readonly(ctx.accounts.mint.key(), false),
-            AccountMeta::new(ctx.accounts.investor_token_account.key(), false),
-            AccountMeta::new_readonly(ctx.accounts.structured_product.key(), true),
-            AccountMeta::new_readonly(ctx.accounts.extra_account_meta_list.key(), false),
-            AccountMeta::new_readonly(ctx.accounts.snapshot_config.key(), false),
-            AccountMeta::new(
-                ctx.accounts.program_token_snapshot_balances_account.key(),
-                false,
-            ),
-            AccountMeta::new(
-                ctx.accounts.investor_token_snapshot_balances_account.key(),
-                false,
-            ),
-            AccountMeta::new_readonly(ctx.accounts.snapshot_transfer_hook_program.key(), false),
-        ];
-        let cpi_account_infos = vec![
-            ctx.accounts.program_token_account.to_account_info(),
-            ctx.accounts.mint.to_account_info(),
-            ctx.accounts.investor_token_account.to_account_info(),
-            ctx.accounts.structured_product.to_account_info(),
-            ctx.accounts.extra_account_meta_list.clone(),
-            ctx.accounts.snapshot_config.clone(),
-            ctx.accounts.program_token_snapshot_balances_account.clone(),
-            ctx.accounts
-                .investor_token_snapshot_balances_account
-                .clone(),
-            ctx.accounts
+        transfer_with_snapshot_hook(
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.program_token_account.to_account_info(),
+            &ctx.accounts.mint.to_account_info(),
+            &ctx.accounts.investor_token_account.to_account_info(),
+            &ctx.accounts.structured_product.to_account_info(),
+            &ctx.accounts.extra_account_meta_list,
+            &ctx.accounts.snapshot_config,
+            &ctx.accounts.program_token_snapshot_balances_account,
+            &ctx.accounts.investor_token_snapshot_balances_account,
+            &ctx.accounts
                 .snapshot_transfer_hook_program
                 .to_account_info(),
-        ];
-
-        let transfer_checked_ix = Instruction {
-            program_id: token_program.key(),
-            accounts,
-            data,
-        };
-
-        //
-        // msg!("Adding extra account meta list");
-        // ExtraAccountMetaList::add_to_cpi_instruction::<ExecuteInstruction>(
-        //     &mut transfer_checked_ix,
-        //     &mut cpi_account_infos,
-        //     &**ctx.accounts.extra_account_meta_list.data.borrow_mut(),
-        //     remaining_account_infos.as_slice(),
-        // )?;
-
-        msg!("Invoke transfer_checked ix!");
-        solana_program::program::invoke_signed(
-            &transfer_checked_ix,
-            &cpi_account_infos,
+            ctx.accounts.structured_product.supply,
             &[&signer_seeds[..]],
         )?;
 
+        require!(
+            ctx.remaining_accounts.len() == reserved_payment_date_offsets.len(),
+            StructuredProductError::InvalidReservationAccounts
+        );
+        if !reserved_payment_date_offsets.is_empty() {
+            let (
+                Some(treasury_wallet),
+                Some(withdrawal_authorization),
+                Some(reservation_mint),
+                Some(mint_ledger),
+                Some(treasury_wallet_program),
+            ) = (
+                &ctx.accounts.treasury_wallet,
+                &ctx.accounts.withdrawal_authorization,
+                &ctx.accounts.reservation_mint,
+                &ctx.accounts.mint_ledger,
+                &ctx.accounts.treasury_wallet_program,
+            )
+            else {
+                return err!(StructuredProductError::InvalidReservationAccounts);
+            };
+            require!(
+                ctx.accounts.structured_product.issuer_treasury_wallet == treasury_wallet.key(),
+                StructuredProductError::Unauthorized
+            );
+
+            let structured_product_key = ctx.accounts.structured_product.key();
+            for (payment_info, payment_date_offset) in ctx
+                .remaining_accounts
+                .iter()
+                .zip(reserved_payment_date_offsets)
+            {
+                let payment = Account::<Payment>::try_from(payment_info)?;
+                let payment_key = Pubkey::create_program_address(
+                    &[
+                        structured_product_key.as_ref(),
+                        &[payment.principal.into()],
+                        &payment_date_offset.to_le_bytes(),
+                        &[payment.bump],
+                    ],
+                    &crate::ID,
+                )
+                .map_err(|_| StructuredProductError::InvalidReservationAccounts)?;
+                require!(
+                    payment_key == payment_info.key()
+                        && payment.price_authority.is_none()
+                        && payment.payment_mint == reservation_mint.key(),
+                    StructuredProductError::InvalidReservationAccounts
+                );
+                let amount = payment
+                    .price_per_unit
+                    .ok_or(StructuredProductError::PaymentAmountNotSet)?
+                    .checked_mul(ctx.accounts.structured_product.supply)
+                    .ok_or(StructuredProductError::InvalidAmount)?;
+
+                let cpi_accounts = Reserve {
+                    payer: ctx.accounts.issuer.to_account_info(),
+                    authority: ctx.accounts.structured_product.to_account_info(),
+                    treasury_wallet: treasury_wallet.to_account_info(),
+                    withdraw_authorization: withdrawal_authorization.to_account_info(),
+                    mint: reservation_mint.to_account_info(),
+                    mint_ledger: mint_ledger.to_account_info(),
+                    system_program: ctx.accounts.system_program.to_account_info(),
+                };
+
+                treasury_wallet::cpi::reserve(
+                    CpiContext::new_with_signer(
+                        treasury_wallet_program.to_account_info(),
+                        cpi_accounts,
+                        &[&signer_seeds[..]],
+                    ),
+                    payment_date_offset,
+                    amount,
+                )?;
+            }
+        }
+
         let structured_product = &mut ctx.accounts.structured_product;
         structured_product.issuance_date = Some(Clock::get()?.unix_timestamp);
 
@@ -546,164 +806,937 @@ pub mod structured_product {
         Ok(())
     }
 
-    pub fn withdraw_issuance_proceeds(ctx: Context<WithdrawIssuanceProceeds>) -> Result<()> {
+    pub fn add_subscriber(ctx: Context<AddSubscriber>) -> Result<()> {
         require!(
-            ctx.accounts.structured_product.issuance_date.is_some(),
+            ctx.accounts.authority.key() == ctx.accounts.structured_product.authority.key(),
             StructuredProductError::Unauthorized
         );
         require!(
-            ctx.accounts.structured_product.issuer == ctx.accounts.issuer.key(),
-            StructuredProductError::Unauthorized
+            !ctx.accounts.structured_product.subscriptions_closed,
+            StructuredProductError::SubscriptionsClosed
         );
         require!(
-            ctx.accounts.structured_product_token_account.amount
-                == ctx.accounts.mint.supply
-                    * ctx
-                        .accounts
-                        .structured_product
-                        .issuance_payment_amount_per_unit,
-            StructuredProductError::Unauthorized
+            ctx.accounts.structured_product.issuance_date.is_none(),
+            StructuredProductError::AlreadyIssued
+        );
+        require!(
+            !ctx.accounts.structured_product.cancelled,
+            StructuredProductError::Cancelled
+        );
+
+        let subscription = &mut ctx.accounts.subscription;
+        subscription.investor = ctx.accounts.investor.key();
+        subscription.units = 0;
+        subscription.allocation = None;
+        subscription.bump = ctx.bumps.subscription;
+        Ok(())
+    }
+
+    pub fn subscribe(ctx: Context<Subscribe>, units: u64) -> Result<()> {
+        require!(units > 0, StructuredProductError::InvalidAmount);
+        require!(
+            !ctx.accounts.structured_product.paid,
+            StructuredProductError::AlreadyPaid
+        );
+        require!(
+            !ctx.accounts.structured_product.subscriptions_closed,
+            StructuredProductError::SubscriptionsClosed
+        );
+        require!(
+            ctx.accounts.structured_product.issuance_date.is_none(),
+            StructuredProductError::AlreadyIssued
+        );
+        require!(
+            !ctx.accounts.structured_product.cancelled,
+            StructuredProductError::Cancelled
         );
+        ctx.accounts
+            .structured_product
+            .check_offer_not_expired(Clock::get()?.unix_timestamp)?;
+
         let cpi_program = ctx.accounts.token_program.to_account_info();
 
-        let cpi_accounts = token_2022::TransferChecked {
+        let cpi_accounts = token_interface::TransferChecked {
             from: ctx
+                .accounts
+                .investor_payment_token_account
+                .to_account_info(),
+            to: ctx
                 .accounts
                 .structured_product_token_account
                 .to_account_info(),
-            to: ctx.accounts.beneficiary_token_account.to_account_info(),
             mint: ctx.accounts.payment_mint.to_account_info(),
-            authority: ctx.accounts.structured_product.to_account_info(),
+            authority: ctx.accounts.investor.to_account_info(),
         };
 
-        let mint_key = ctx.accounts.mint.key();
-
-        let seeds = &[mint_key.as_ref(), &[ctx.accounts.structured_product.bump]];
-
-        token_2022::transfer_checked(
-            CpiContext::new_with_signer(cpi_program, cpi_accounts, &[&seeds[..]]),
-            ctx.accounts.structured_product_token_account.amount,
+        token_interface::transfer_checked(
+            CpiContext::new(cpi_program, cpi_accounts),
+            units
+                * ctx
+                    .accounts
+                    .structured_product
+                    .issuance_payment_amount_per_unit,
             ctx.accounts.payment_mint.decimals,
         )?;
 
+        let subscription = &mut ctx.accounts.subscription;
+        subscription.units += units;
+
+        let structured_product = &mut ctx.accounts.structured_product;
+        structured_product.total_subscribed += units;
         Ok(())
     }
 
-    pub fn set_payment_price(
-        ctx: Context<SetPaymentPrice>,
-        _payment_date_offset: i64,
-        price_per_unit: u64,
-    ) -> Result<()> {
+    pub fn close_subscriptions(ctx: Context<CloseSubscriptions>) -> Result<()> {
         require!(
-            Some(ctx.accounts.authority.key()) == ctx.accounts.payment.price_authority,
+            ctx.accounts.issuer.key() == ctx.accounts.structured_product.issuer.key(),
             StructuredProductError::Unauthorized
         );
-
-        let current_time = Clock::get()?.unix_timestamp;
-        msg!(
-            "Payment date: {}, current time: {} price: {}",
-            _payment_date_offset,
-            current_time,
-            price_per_unit
-        );
-
         require!(
-            _payment_date_offset <= current_time,
-            StructuredProductError::DateNotInPast
+            ctx.accounts.structured_product.principal_defined,
+            StructuredProductError::PrincipalUndefined
         );
-
         require!(
-            ctx.accounts.payment.price_per_unit.is_none(),
-            StructuredProductError::PaymentAmountAlreadySet
+            ctx.accounts.structured_product.issuance_date.is_none(),
+            StructuredProductError::AlreadyIssued
         );
-
-        let payment = &mut ctx.accounts.payment;
-        payment.price_per_unit = Some(price_per_unit);
-        Ok(())
-    }
-
-    pub fn pull_payment(ctx: Context<PullPayment>, _payment_date_offset: i64) -> Result<()> {
-        let payment = &mut ctx.accounts.payment;
-        // Validate maturity date
-
         require!(
-            payment.price_per_unit.is_some(),
-            StructuredProductError::PaymentAmountNotSet
+            !ctx.accounts.structured_product.cancelled,
+            StructuredProductError::Cancelled
         );
-
-        require!(!payment.paid, StructuredProductError::Unpaid);
-
+        // after expiry the book can only be cancelled and refunded
+        ctx.accounts
+            .structured_product
+            .check_offer_not_expired(Clock::get()?.unix_timestamp)?;
+        ctx.accounts
+            .structured_product
+            .check_legal_approval(&ctx.accounts.legal_approver)?;
         require!(
-            ctx.accounts.structured_product.issuer_treasury_wallet
-                == ctx.accounts.treasury_wallet.key(),
-            StructuredProductError::Unauthorized
+            ctx.accounts.structured_product.total_subscribed > 0,
+            StructuredProductError::NoSubscriptions
         );
 
-        let cpi_program = ctx.accounts.treasury_wallet_program.to_account_info();
-
         let mint_key = ctx.accounts.mint.key();
-        let seeds = &[mint_key.as_ref(), &[ctx.accounts.structured_product.bump]];
+        let signer_seeds = &[mint_key.as_ref(), &[ctx.accounts.structured_product.bump]];
 
-        let cpi_accounts = Withdraw {
-            mint: ctx.accounts.payment_mint.to_account_info(),
-            treasury_wallet: ctx.accounts.treasury_wallet.to_account_info(),
-            treasury_authority: ctx.accounts.treasury_authority.to_account_info(),
-            treasury_wallet_token_account: ctx
+        let snapshot_program = &ctx.accounts.snapshot_transfer_hook_program;
+
+        msg!("Creating program snapshot balances account");
+        let cpi_accounts = transfer_snapshot_hook::cpi::accounts::InitSnapshotBalancesAccount {
+            snapshot_config: ctx.accounts.snapshot_config.to_account_info(),
+            snapshot_balances: ctx
                 .accounts
-                .treasury_wallet_token_account
+                .program_token_snapshot_balances_account
                 .to_account_info(),
-            destination: ctx.accounts.payment_token_account.to_account_info(),
-            withdraw_authorization: ctx.accounts.withdrawal_authorization.to_account_info(),
+            owner: ctx.accounts.structured_product.to_account_info(),
+            token_account: ctx.accounts.program_token_account.to_account_info(),
+            payer: ctx.accounts.issuer.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+        };
+
+        transfer_snapshot_hook::cpi::init_snapshot_balances_account(CpiContext::new(
+            snapshot_program.to_account_info(),
+            cpi_accounts,
+        ))?;
+
+        msg!("Activating snapshot hook program");
+        let cpi_accounts = transfer_snapshot_hook::cpi::accounts::ActivateSnapshots {
+            snapshot_config: ctx.accounts.snapshot_config.to_account_info(),
             authority: ctx.accounts.structured_product.to_account_info(),
-            token_program: ctx.accounts.token_program.to_account_info(),
         };
-        msg!("calling withdraw");
 
-        treasury_wallet::cpi::withdraw(
-            CpiContext::new_with_signer(cpi_program, cpi_accounts, &[&seeds[..]]),
-            ctx.accounts.mint.supply * payment.price_per_unit.unwrap(),
-        )?;
+        transfer_snapshot_hook::cpi::activate(CpiContext::new_with_signer(
+            snapshot_program.to_account_info(),
+            cpi_accounts,
+            &[&signer_seeds[..]],
+        ))?;
+
+        let structured_product = &mut ctx.accounts.structured_product;
+        structured_product.subscriptions_closed = true;
+        structured_product.issuance_date = Some(Clock::get()?.unix_timestamp);
+
+        msg!(
+            "Subscriptions closed, subscribed: {}, supply: {}",
+            structured_product.total_subscribed,
+            structured_product.supply
+        );
+
         Ok(())
     }
 
-    pub fn settle_payment(ctx: Context<SettlePayment>, payment_date_offset: i64) -> Result<()> {
+    pub fn issue_subscription(ctx: Context<IssueSubscription>) -> Result<()> {
         require!(
-            ctx.accounts.payment.price_per_unit.is_some(),
-            StructuredProductError::PaymentAmountNotSet
+            ctx.accounts.structured_product.subscriptions_closed,
+            StructuredProductError::SubscriptionsNotClosed
         );
         require!(
-            !ctx.accounts.payment.paid,
-            StructuredProductError::AlreadyPaid
+            ctx.accounts.subscription.allocation.is_none(),
+            StructuredProductError::AlreadyIssued
         );
 
-        let snapshot_config = &ctx.accounts.snapshot_config;
-
-        // find index of snapshot with payment_date_offset
-        let snapshot_index = snapshot_config
-            .snapshots
-            .iter()
-            .position(|&x| x == payment_date_offset);
-
-        // this should never happen...
-        require!(
-            snapshot_index.is_some(),
-            StructuredProductError::InvalidPaymentDate
+        let allocation = calc_allocation(
+            ctx.accounts.subscription.units,
+            ctx.accounts.structured_product.total_subscribed,
+            ctx.accounts.structured_product.supply,
         );
 
-        let snapshot_balance = ctx
-            .accounts
-            .beneficiary_snapshot_balances_account
-            .balance_at_snapshot(snapshot_index.unwrap());
-
-        require!(
-            snapshot_balance > 0,
-            StructuredProductError::InsufficientBalance
+        msg!(
+            "Subscribed: {}, allocated: {}",
+            ctx.accounts.subscription.units,
+            allocation
         );
 
-        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let mint_key = ctx.accounts.mint.key();
+        let signer_seeds = &[mint_key.as_ref(), &[ctx.accounts.structured_product.bump]];
 
-        let cpi_accounts = token_2022::TransferChecked {
-            from: ctx.accounts.payment_token_account.to_account_info(),
+        msg!("Creating investors snapshot balances account");
+        let cpi_accounts = transfer_snapshot_hook::cpi::accounts::InitSnapshotBalancesAccount {
+            snapshot_config: ctx.accounts.snapshot_config.to_account_info(),
+            snapshot_balances: ctx
+                .accounts
+                .investor_token_snapshot_balances_account
+                .to_account_info(),
+            owner: ctx.accounts.investor.to_account_info(),
+            token_account: ctx.accounts.investor_token_account.to_account_info(),
+            payer: ctx.accounts.payer.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+        };
+
+        transfer_snapshot_hook::cpi::init_snapshot_balances_account(CpiContext::new(
+            ctx.accounts
+                .snapshot_transfer_hook_program
+                .to_account_info(),
+            cpi_accounts,
+        ))?;
+
+        if allocation > 0 {
+            let cpi_accounts = token_2022::MintTo {
+                mint: ctx.accounts.mint.to_account_info(),
+                to: ctx.accounts.program_token_account.to_account_info(),
+                authority: ctx.accounts.structured_product.to_account_info(),
+            };
+
+            token_2022::mint_to(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    cpi_accounts,
+                    &[&signer_seeds[..]],
+                ),
+                allocation,
+            )?;
+
+            transfer_with_snapshot_hook(
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.program_token_account.to_account_info(),
+                &ctx.accounts.mint.to_account_info(),
+                &ctx.accounts.investor_token_account.to_account_info(),
+                &ctx.accounts.structured_product.to_account_info(),
+                &ctx.accounts.extra_account_meta_list,
+                &ctx.accounts.snapshot_config,
+                &ctx.accounts.program_token_snapshot_balances_account,
+                &ctx.accounts.investor_token_snapshot_balances_account,
+                &ctx.accounts
+                    .snapshot_transfer_hook_program
+                    .to_account_info(),
+                allocation,
+                &[&signer_seeds[..]],
+            )?;
+        }
+
+        let unallocated = ctx.accounts.subscription.units - allocation;
+
+        if unallocated > 0 {
+            msg!("Refunding {} unallocated units", unallocated);
+            let cpi_accounts = token_interface::TransferChecked {
+                from: ctx
+                    .accounts
+                    .structured_product_token_account
+                    .to_account_info(),
+                to: ctx
+                    .accounts
+                    .investor_payment_token_account
+                    .to_account_info(),
+                mint: ctx.accounts.payment_mint.to_account_info(),
+                authority: ctx.accounts.structured_product.to_account_info(),
+            };
+
+            token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    ctx.accounts.payment_token_program.to_account_info(),
+                    cpi_accounts,
+                    &[&signer_seeds[..]],
+                ),
+                unallocated
+                    * ctx
+                        .accounts
+                        .structured_product
+                        .issuance_payment_amount_per_unit,
+                ctx.accounts.payment_mint.decimals,
+            )?;
+        }
+
+        let subscription = &mut ctx.accounts.subscription;
+        subscription.allocation = Some(allocation);
+
+        let structured_product = &mut ctx.accounts.structured_product;
+        structured_product.total_issued += subscription.units;
+
+        Ok(())
+    }
+
+    pub fn cancel_issuance(ctx: Context<CancelIssuance>) -> Result<()> {
+        require!(
+            ctx.accounts.structured_product.issuance_date.is_none(),
+            StructuredProductError::AlreadyIssued
+        );
+        require!(
+            !ctx.accounts.structured_product.cancelled,
+            StructuredProductError::Cancelled
+        );
+
+        let structured_product = &ctx.accounts.structured_product;
+
+        let signed_by_issuer = matches!(&ctx.accounts.issuer, Some(issuer) if issuer.key() == structured_product.issuer);
+        // A paid single investor offer needs the consent of the investor. Subscription books
+        // have no single counterparty, the issuer can cancel them and subscribers get refunded.
+        let mutually_cancelled = signed_by_issuer
+            && (!structured_product.paid
+                || matches!(&ctx.accounts.investor, Some(investor) if investor.key() == structured_product.investor));
+
+        let expired = match structured_product.offer_expiry {
+            Some(offer_expiry) => offer_expiry < Clock::get()?.unix_timestamp,
+            None => false,
+        };
+
+        require!(
+            mutually_cancelled || expired,
+            StructuredProductError::OfferNotExpired
+        );
+
+        let structured_product = &mut ctx.accounts.structured_product;
+        structured_product.cancelled = true;
+
+        msg!("Offer cancelled");
+
+        Ok(())
+    }
+
+    pub fn refund_issuance(ctx: Context<RefundIssuance>) -> Result<()> {
+        require!(
+            ctx.accounts.structured_product.cancelled,
+            StructuredProductError::NotCancelled
+        );
+        require!(
+            ctx.accounts.structured_product.paid,
+            StructuredProductError::Unpaid
+        );
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+
+        let cpi_accounts = token_interface::TransferChecked {
+            from: ctx
+                .accounts
+                .structured_product_token_account
+                .to_account_info(),
+            to: ctx.accounts.payer_token_account.to_account_info(),
+            mint: ctx.accounts.payment_mint.to_account_info(),
+            authority: ctx.accounts.structured_product.to_account_info(),
+        };
+
+        let mint_key = ctx.accounts.mint.key();
+        let seeds = &[mint_key.as_ref(), &[ctx.accounts.structured_product.bump]];
+
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(cpi_program, cpi_accounts, &[&seeds[..]]),
+            ctx.accounts.structured_product.supply
+                * ctx
+                    .accounts
+                    .structured_product
+                    .issuance_payment_amount_per_unit,
+            ctx.accounts.payment_mint.decimals,
+        )?;
+
+        let structured_product = &mut ctx.accounts.structured_product;
+        structured_product.paid = false;
+        Ok(())
+    }
+
+    pub fn refund_subscription(ctx: Context<RefundSubscription>) -> Result<()> {
+        require!(
+            ctx.accounts.structured_product.cancelled,
+            StructuredProductError::NotCancelled
+        );
+        require!(
+            ctx.accounts.subscription.units > 0,
+            StructuredProductError::InvalidAmount
+        );
+
+        let units = ctx.accounts.subscription.units;
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+
+        let cpi_accounts = token_interface::TransferChecked {
+            from: ctx
+                .accounts
+                .structured_product_token_account
+                .to_account_info(),
+            to: ctx
+                .accounts
+                .investor_payment_token_account
+                .to_account_info(),
+            mint: ctx.accounts.payment_mint.to_account_info(),
+            authority: ctx.accounts.structured_product.to_account_info(),
+        };
+
+        let mint_key = ctx.accounts.mint.key();
+        let seeds = &[mint_key.as_ref(), &[ctx.accounts.structured_product.bump]];
+
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(cpi_program, cpi_accounts, &[&seeds[..]]),
+            units
+                * ctx
+                    .accounts
+                    .structured_product
+                    .issuance_payment_amount_per_unit,
+            ctx.accounts.payment_mint.decimals,
+        )?;
+
+        let subscription = &mut ctx.accounts.subscription;
+        subscription.units = 0;
+
+        let structured_product = &mut ctx.accounts.structured_product;
+        structured_product.total_subscribed -= units;
+        Ok(())
+    }
+
+    pub fn withdraw_issuance_proceeds(ctx: Context<WithdrawIssuanceProceeds>) -> Result<()> {
+        require!(
+            ctx.accounts.structured_product.issuance_date.is_some(),
+            StructuredProductError::Unauthorized
+        );
+        require!(
+            ctx.accounts.structured_product.issuer == ctx.accounts.issuer.key(),
+            StructuredProductError::Unauthorized
+        );
+        // the proceeds of subscriptions are only known once all of them are issued
+        ctx.accounts.structured_product.check_book_issued()?;
+        require!(
+            ctx.accounts.structured_product_token_account.amount
+                == ctx.accounts.mint.supply
+                    * ctx
+                        .accounts
+                        .structured_product
+                        .issuance_payment_amount_per_unit,
+            StructuredProductError::Unauthorized
+        );
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+
+        let cpi_accounts = token_interface::TransferChecked {
+            from: ctx
+                .accounts
+                .structured_product_token_account
+                .to_account_info(),
+            to: ctx.accounts.beneficiary_token_account.to_account_info(),
+            mint: ctx.accounts.payment_mint.to_account_info(),
+            authority: ctx.accounts.structured_product.to_account_info(),
+        };
+
+        let mint_key = ctx.accounts.mint.key();
+
+        let seeds = &[mint_key.as_ref(), &[ctx.accounts.structured_product.bump]];
+
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(cpi_program, cpi_accounts, &[&seeds[..]]),
+            ctx.accounts.structured_product_token_account.amount,
+            ctx.accounts.payment_mint.decimals,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn add_call_date(
+        ctx: Context<AddCallDate>,
+        call_date_offset: i64,
+        call_price_per_unit: u64,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.structured_product.authority.key(),
+            StructuredProductError::Unauthorized
+        );
+        require!(
+            ctx.accounts.structured_product.issuance_date.is_none(),
+            StructuredProductError::AlreadyIssued
+        );
+
+        // Calls can only be exercised on coupon dates
+        let snapshot_config = &ctx.accounts.snapshot_config;
+        require!(
+            snapshot_config.snapshots[..snapshot_config.defined_snapshots as usize]
+                .contains(&call_date_offset),
+            StructuredProductError::InvalidPaymentDate
+        );
+
+        let call_date = &mut ctx.accounts.call_date;
+        call_date.call_price_per_unit = call_price_per_unit;
+        call_date.bump = ctx.bumps.call_date;
+        Ok(())
+    }
+
+    // Redeems the product early at the call price.
+    // All coupon payments after the call date have to be passed as remaining accounts, in order.
+    pub fn exercise_call<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ExerciseCall<'info>>,
+        call_date_offset: i64,
+        maturity_date_offset: i64,
+    ) -> Result<()> {
+        let structured_product = &ctx.accounts.structured_product;
+        require!(
+            ctx.accounts.issuer.key() == structured_product.issuer,
+            StructuredProductError::Unauthorized
+        );
+        require!(
+            structured_product.call_date_offset.is_none(),
+            StructuredProductError::AlreadyCalled
+        );
+
+        let issuance_date = structured_product
+            .issuance_date
+            .ok_or(StructuredProductError::NotIssued)?;
+
+        require!(
+            Clock::get()?.unix_timestamp <= issuance_date + call_date_offset,
+            StructuredProductError::DateNotInFuture
+        );
+
+        let snapshot_config = &ctx.accounts.snapshot_config;
+        let snapshots = &snapshot_config.snapshots[..snapshot_config.defined_snapshots as usize];
+
+        require!(
+            snapshots.last() == Some(&maturity_date_offset),
+            StructuredProductError::InvalidPaymentDate
+        );
+
+        let call_index = snapshots
+            .iter()
+            .position(|&x| x == call_date_offset)
+            .ok_or(StructuredProductError::InvalidPaymentDate)?;
+
+        let cancelled_payment_offsets = &snapshots[call_index + 1..];
+
+        require!(
+            ctx.remaining_accounts.len() == cancelled_payment_offsets.len(),
+            StructuredProductError::InvalidPaymentDate
+        );
+
+        let structured_product_key = structured_product.key();
+
+        for (payment_info, payment_date_offset) in
+            ctx.remaining_accounts.iter().zip(cancelled_payment_offsets)
+        {
+            let (payment_key, _) = Pubkey::find_program_address(
+                &[
+                    structured_product_key.as_ref(),
+                    &[false.into()],
+                    &payment_date_offset.to_le_bytes(),
+                ],
+                ctx.program_id,
+            );
+            require!(
+                payment_info.key() == payment_key,
+                StructuredProductError::InvalidPaymentDate
+            );
+
+            let mut payment = Account::<Payment>::try_from(payment_info)?;
+            payment.cancelled = true;
+            payment.exit(ctx.program_id)?;
+
+            msg!("Cancelled payment at {}", payment_date_offset);
+        }
+
+        msg!("Truncating snapshots after {}", call_date_offset);
+        let mint_key = ctx.accounts.mint.key();
+        let signer_seeds = &[mint_key.as_ref(), &[ctx.accounts.structured_product.bump]];
+
+        let cpi_accounts = transfer_snapshot_hook::cpi::accounts::TruncateSnapshots {
+            snapshot_config: ctx.accounts.snapshot_config.to_account_info(),
+            authority: ctx.accounts.structured_product.to_account_info(),
+        };
+
+        transfer_snapshot_hook::cpi::truncate_snapshots(
+            CpiContext::new_with_signer(
+                ctx.accounts
+                    .snapshot_transfer_hook_program
+                    .to_account_info(),
+                cpi_accounts,
+                &[&signer_seeds[..]],
+            ),
+            call_date_offset,
+        )?;
+
+        let principal_payment = &mut ctx.accounts.principal_payment;
+        principal_payment.price_authority = None;
+        principal_payment.price_per_unit = Some(ctx.accounts.call_date.call_price_per_unit);
+        principal_payment.fixing_status = FixingStatus::Final;
+        principal_payment.early_redemption_date_offset = Some(call_date_offset);
+
+        let structured_product = &mut ctx.accounts.structured_product;
+        structured_product.call_date_offset = Some(call_date_offset);
+
+        msg!("Called at {}", call_date_offset);
+
+        Ok(())
+    }
+
+    pub fn set_payment_price(
+        ctx: Context<SetPaymentPrice>,
+        _payment_date_offset: i64,
+        price_per_unit: u64,
+    ) -> Result<()> {
+        ctx.accounts.payment.check_price_settable(
+            ctx.accounts.authority.key,
+            ctx.accounts.structured_product.issuance_date(),
+            _payment_date_offset,
+            Clock::get()?.unix_timestamp,
+        )?;
+
+        msg!(
+            "Payment date: {}, price: {}",
+            _payment_date_offset,
+            price_per_unit
+        );
+
+        let dispute_window = ctx.accounts.structured_product.dispute_window;
+        let payment = &mut ctx.accounts.payment;
+        payment.price_per_unit = Some(price_per_unit);
+        payment.fixing_status =
+            FixingStatus::proposed(dispute_window, Clock::get()?.unix_timestamp);
+        Ok(())
+    }
+
+    // Physically settled payments deliver delivery_per_unit tokens of the delivery mint
+    // and pay the cash remainder price_per_unit per unit
+    pub fn set_payment_delivery(
+        ctx: Context<SetPaymentPrice>,
+        _payment_date_offset: i64,
+        delivery_per_unit: u64,
+        price_per_unit: u64,
+    ) -> Result<()> {
+        ctx.accounts.payment.check_price_settable(
+            ctx.accounts.authority.key,
+            ctx.accounts.structured_product.issuance_date(),
+            _payment_date_offset,
+            Clock::get()?.unix_timestamp,
+        )?;
+        require!(
+            ctx.accounts.payment.delivery_mint.is_some(),
+            StructuredProductError::PhysicalDeliveryNotEnabled
+        );
+
+        msg!(
+            "Payment date: {}, delivery: {}, price: {}",
+            _payment_date_offset,
+            delivery_per_unit,
+            price_per_unit
+        );
+
+        let dispute_window = ctx.accounts.structured_product.dispute_window;
+        let payment = &mut ctx.accounts.payment;
+        payment.delivery_per_unit = Some(delivery_per_unit);
+        payment.price_per_unit = Some(price_per_unit);
+        payment.fixing_status =
+            FixingStatus::proposed(dispute_window, Clock::get()?.unix_timestamp);
+        Ok(())
+    }
+
+    // Freezes settlement of a proposed fixing until the calculation agent corrects it.
+    // The investor, the issuer and any holder of the payment can dispute.
+    pub fn dispute_payment_price(
+        ctx: Context<DisputePaymentPrice>,
+        _payment_date_offset: i64,
+    ) -> Result<()> {
+        let structured_product = &ctx.accounts.structured_product;
+        let disputer = ctx.accounts.disputer.key();
+
+        // holders prove their stake with their snapshot balance of the payment
+        if disputer != structured_product.investor && disputer != structured_product.issuer {
+            let (
+                Some(snapshot_config),
+                Some(disputer_token_account),
+                Some(disputer_snapshot_balances_account),
+            ) = (
+                &ctx.accounts.snapshot_config,
+                &ctx.accounts.disputer_token_account,
+                &ctx.accounts.disputer_snapshot_balances_account,
+            )
+            else {
+                return err!(StructuredProductError::Unauthorized);
+            };
+
+            let mint_key = ctx.accounts.mint.key();
+            let (snapshot_config_key, _) = Pubkey::find_program_address(
+                &[b"snapshots", mint_key.as_ref()],
+                &transfer_snapshot_hook::ID,
+            );
+            let (snapshot_balances_key, _) = Pubkey::find_program_address(
+                &[mint_key.as_ref(), disputer_token_account.key().as_ref()],
+                &transfer_snapshot_hook::ID,
+            );
+            require!(
+                disputer_token_account.mint == mint_key
+                    && disputer_token_account.owner == disputer
+                    && snapshot_config.key() == snapshot_config_key
+                    && disputer_snapshot_balances_account.key() == snapshot_balances_key,
+                StructuredProductError::Unauthorized
+            );
+
+            // early redemptions are paid out on the balances of the call date
+            let snapshot_date_offset = ctx
+                .accounts
+                .payment
+                .early_redemption_date_offset
+                .unwrap_or(_payment_date_offset);
+            let snapshot_index = snapshot_config
+                .snapshots
+                .iter()
+                .position(|&x| x == snapshot_date_offset)
+                .ok_or(StructuredProductError::InvalidPaymentDate)?;
+            require!(
+                disputer_snapshot_balances_account.balance_at_snapshot(snapshot_index) > 0,
+                StructuredProductError::InsufficientBalance
+            );
+        }
+
+        let proposed_at = match ctx.accounts.payment.fixing_status {
+            FixingStatus::Proposed { proposed_at } => proposed_at,
+            _ => return err!(StructuredProductError::NotDisputable),
+        };
+        require!(
+            Clock::get()?.unix_timestamp
+                < proposed_at.saturating_add(structured_product.dispute_window),
+            StructuredProductError::DisputeWindowClosed
+        );
+
+        msg!("Payment date: {} disputed", _payment_date_offset);
+
+        let payment = &mut ctx.accounts.payment;
+        payment.fixing_status = FixingStatus::Disputed;
+        Ok(())
+    }
+
+    // The calculation agent can correct a fixing that is not final yet, the correction is final
+    pub fn correct_payment_price(
+        ctx: Context<CorrectPaymentPrice>,
+        _payment_date_offset: i64,
+        price_per_unit: u64,
+        delivery_per_unit: Option<u64>,
+    ) -> Result<()> {
+        let structured_product = &ctx.accounts.structured_product;
+        require!(
+            Some(ctx.accounts.calculation_agent.key()) == structured_product.calculation_agent,
+            StructuredProductError::Unauthorized
+        );
+        let payment = &ctx.accounts.payment;
+        require!(
+            payment.price_per_unit.is_some(),
+            StructuredProductError::PaymentAmountNotSet
+        );
+        require!(
+            !payment.fixing_status.is_final(
+                structured_product.dispute_window,
+                Clock::get()?.unix_timestamp
+            ),
+            StructuredProductError::NotDisputable
+        );
+        require!(
+            delivery_per_unit.is_none() || payment.delivery_mint.is_some(),
+            StructuredProductError::PhysicalDeliveryNotEnabled
+        );
+
+        msg!(
+            "Payment date: {}, corrected price: {}, delivery: {:?}",
+            _payment_date_offset,
+            price_per_unit,
+            delivery_per_unit
+        );
+
+        let payment = &mut ctx.accounts.payment;
+        payment.price_per_unit = Some(price_per_unit);
+        payment.delivery_per_unit = delivery_per_unit;
+        payment.fixing_status = FixingStatus::Final;
+        Ok(())
+    }
+
+    pub fn pull_payment(ctx: Context<PullPayment>, _payment_date_offset: i64) -> Result<()> {
+        let payment = &mut ctx.accounts.payment;
+        // Validate maturity date
+
+        require!(
+            payment.price_per_unit.is_some(),
+            StructuredProductError::PaymentAmountNotSet
+        );
+        payment.check_fixing_final(
+            ctx.accounts.structured_product.dispute_window,
+            Clock::get()?.unix_timestamp,
+        )?;
+
+        require!(!payment.paid, StructuredProductError::Unpaid);
+
+        require!(!payment.cancelled, StructuredProductError::PaymentCancelled);
+
+        require!(
+            ctx.accounts.structured_product.issuer_treasury_wallet
+                == ctx.accounts.treasury_wallet.key(),
+            StructuredProductError::Unauthorized
+        );
+        // payments are funded for the whole supply
+        ctx.accounts.structured_product.check_book_issued()?;
+
+        let cpi_program = ctx.accounts.treasury_wallet_program.to_account_info();
+
+        let mint_key = ctx.accounts.mint.key();
+        let seeds = &[mint_key.as_ref(), &[ctx.accounts.structured_product.bump]];
+
+        let cpi_accounts = Withdraw {
+            mint: ctx.accounts.payment_mint.to_account_info(),
+            treasury_wallet: ctx.accounts.treasury_wallet.to_account_info(),
+            treasury_authority: ctx.accounts.treasury_authority.to_account_info(),
+            treasury_wallet_token_account: ctx
+                .accounts
+                .treasury_wallet_token_account
+                .to_account_info(),
+            destination: ctx.accounts.payment_token_account.to_account_info(),
+            destination_owner: Some(payment.to_account_info()),
+            withdraw_authorization: ctx.accounts.withdrawal_authorization.to_account_info(),
+            mint_ledger: ctx.accounts.mint_ledger.to_account_info(),
+            authority: ctx.accounts.structured_product.to_account_info(),
+            token_program: ctx.accounts.token_program.to_account_info(),
+        };
+        msg!("calling withdraw");
+
+        treasury_wallet::cpi::withdraw(
+            CpiContext::new_with_signer(cpi_program, cpi_accounts, &[&seeds[..]]),
+            ctx.accounts.mint.supply * payment.price_per_unit.unwrap(),
+        )?;
+        Ok(())
+    }
+
+    // Pulls the tokens to be delivered by a physically settled payment from the treasury wallet
+    pub fn pull_delivery(ctx: Context<PullDelivery>, _payment_date_offset: i64) -> Result<()> {
+        let payment = &ctx.accounts.payment;
+
+        require!(
+            payment.price_per_unit.is_some(),
+            StructuredProductError::PaymentAmountNotSet
+        );
+        payment.check_fixing_final(
+            ctx.accounts.structured_product.dispute_window,
+            Clock::get()?.unix_timestamp,
+        )?;
+        require!(!payment.cancelled, StructuredProductError::PaymentCancelled);
+        require!(
+            payment.delivery_mint == Some(ctx.accounts.delivery_mint.key()),
+            StructuredProductError::InvalidDeliveryAccounts
+        );
+        let delivery_per_unit = payment
+            .delivery_per_unit
+            .ok_or(StructuredProductError::PaymentAmountNotSet)?;
+
+        require!(
+            ctx.accounts.structured_product.issuer_treasury_wallet
+                == ctx.accounts.treasury_wallet.key(),
+            StructuredProductError::Unauthorized
+        );
+        // deliveries are funded for the whole supply
+        ctx.accounts.structured_product.check_book_issued()?;
+
+        let cpi_program = ctx.accounts.treasury_wallet_program.to_account_info();
+
+        let mint_key = ctx.accounts.mint.key();
+        let seeds = &[mint_key.as_ref(), &[ctx.accounts.structured_product.bump]];
+
+        let cpi_accounts = Withdraw {
+            mint: ctx.accounts.delivery_mint.to_account_info(),
+            treasury_wallet: ctx.accounts.treasury_wallet.to_account_info(),
+            treasury_authority: ctx.accounts.treasury_authority.to_account_info(),
+            treasury_wallet_token_account: ctx
+                .accounts
+                .treasury_wallet_token_account
+                .to_account_info(),
+            destination: ctx
+                .accounts
+                .payment_delivery_token_account
+                .to_account_info(),
+            destination_owner: Some(ctx.accounts.payment.to_account_info()),
+            withdraw_authorization: ctx.accounts.withdrawal_authorization.to_account_info(),
+            mint_ledger: ctx.accounts.mint_ledger.to_account_info(),
+            authority: ctx.accounts.structured_product.to_account_info(),
+            token_program: ctx.accounts.delivery_token_program.to_account_info(),
+        };
+
+        treasury_wallet::cpi::withdraw(
+            CpiContext::new_with_signer(cpi_program, cpi_accounts, &[&seeds[..]]),
+            ctx.accounts.mint.supply * delivery_per_unit,
+        )?;
+        Ok(())
+    }
+
+    // Physically settled payments also deliver the tokens of the delivery mint,
+    // the delivery accounts are only needed for those.
+    pub fn settle_payment(ctx: Context<SettlePayment>, payment_date_offset: i64) -> Result<()> {
+        require!(
+            ctx.accounts.payment.price_per_unit.is_some(),
+            StructuredProductError::PaymentAmountNotSet
+        );
+        ctx.accounts.payment.check_fixing_final(
+            ctx.accounts.structured_product.dispute_window,
+            Clock::get()?.unix_timestamp,
+        )?;
+        require!(
+            !ctx.accounts.payment.paid,
+            StructuredProductError::AlreadyPaid
+        );
+        require!(
+            !ctx.accounts.payment.cancelled,
+            StructuredProductError::PaymentCancelled
+        );
+
+        let snapshot_config = &ctx.accounts.snapshot_config;
+
+        // early redemptions are paid out on the balances of the call date
+        let snapshot_date_offset = ctx
+            .accounts
+            .payment
+            .early_redemption_date_offset
+            .unwrap_or(payment_date_offset);
+
+        // find index of snapshot with payment_date_offset
+        let snapshot_index = snapshot_config
+            .snapshots
+            .iter()
+            .position(|&x| x == snapshot_date_offset);
+
+        // this should never happen...
+        require!(
+            snapshot_index.is_some(),
+            StructuredProductError::InvalidPaymentDate
+        );
+
+        let snapshot_balance = ctx
+            .accounts
+            .beneficiary_snapshot_balances_account
+            .balance_at_snapshot(snapshot_index.unwrap());
+
+        require!(
+            snapshot_balance > 0,
+            StructuredProductError::InsufficientBalance
+        );
+
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+
+        let cpi_accounts = token_interface::TransferChecked {
+            from: ctx.accounts.payment_token_account.to_account_info(),
             to: ctx
                 .accounts
                 .beneficiary_payment_token_account
@@ -721,12 +1754,55 @@ pub mod structured_product {
             &[ctx.accounts.payment.bump],
         ];
 
-        token_2022::transfer_checked(
+        token_interface::transfer_checked(
             CpiContext::new_with_signer(cpi_program, cpi_accounts, &[&seeds[..]]),
             snapshot_balance * ctx.accounts.payment.price_per_unit.unwrap(),
             ctx.accounts.payment_mint.decimals,
         )?;
 
+        if let Some(delivery_per_unit) = ctx.accounts.payment.delivery_per_unit {
+            let (
+                Some(delivery_mint),
+                Some(payment_delivery_token_account),
+                Some(beneficiary_delivery_token_account),
+                Some(delivery_token_program),
+            ) = (
+                &ctx.accounts.delivery_mint,
+                &ctx.accounts.payment_delivery_token_account,
+                &ctx.accounts.beneficiary_delivery_token_account,
+                &ctx.accounts.delivery_token_program,
+            )
+            else {
+                return err!(StructuredProductError::InvalidDeliveryAccounts);
+            };
+
+            require!(
+                ctx.accounts.payment.delivery_mint == Some(delivery_mint.key())
+                    && payment_delivery_token_account.mint == delivery_mint.key()
+                    && payment_delivery_token_account.owner == ctx.accounts.payment.key()
+                    && beneficiary_delivery_token_account.mint == delivery_mint.key()
+                    && beneficiary_delivery_token_account.owner == ctx.accounts.beneficiary.key(),
+                StructuredProductError::InvalidDeliveryAccounts
+            );
+
+            let cpi_accounts = token_interface::TransferChecked {
+                from: payment_delivery_token_account.to_account_info(),
+                to: beneficiary_delivery_token_account.to_account_info(),
+                mint: delivery_mint.to_account_info(),
+                authority: ctx.accounts.payment.to_account_info(),
+            };
+
+            token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    delivery_token_program.to_account_info(),
+                    cpi_accounts,
+                    &[&seeds[..]],
+                ),
+                snapshot_balance * delivery_per_unit,
+                delivery_mint.decimals,
+            )?;
+        }
+
         let payment_paid = &mut ctx.accounts.payment_paid;
         payment_paid.paid = true;
 
@@ -745,8 +1821,7 @@ pub struct Initialize<'info> {
     /// CHECK: we manually initialize the mint here
     #[account(mut)]
     pub mint: Signer<'info>,
-    // TODO: space calculation
-    #[account(init, seeds=[mint.key().as_ref()], bump, payer=authority, space=200)]
+    #[account(init, seeds=[mint.key().as_ref()], bump, payer=authority, space=StructuredProductConfig::space())]
     pub structured_product: Account<'info, StructuredProductConfig>,
     pub payment_mint: InterfaceAccount<'info, Mint>,
     /// CHECK: validated in initialize_extra_account_meta_list
@@ -810,7 +1885,34 @@ pub struct AddVariablePayment<'info> {
     system_program: Program<'info, System>,
 }
 
-// TODO: handle withdrawal when never issued
+#[derive(Accounts)]
+#[instruction(_payment_date_offset: i64)]
+pub struct SetPhysicalDelivery<'info> {
+    authority: Signer<'info>,
+    mint: InterfaceAccount<'info, Mint>,
+    #[account(seeds=[mint.key().as_ref()], bump=structured_product.bump)]
+    structured_product: Account<'info, StructuredProductConfig>,
+    #[account(mut, seeds=[structured_product.key().as_ref(), &[true.into()], &_payment_date_offset.to_le_bytes()], bump=payment.bump)]
+    payment: Account<'info, Payment>,
+    delivery_mint: InterfaceAccount<'info, Mint>,
+}
+
+#[derive(Accounts)]
+pub struct SetLegalApprover<'info> {
+    pub authority: Signer<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, seeds=[mint.key().as_ref()], bump=structured_product.bump)]
+    pub structured_product: Account<'info, StructuredProductConfig>,
+}
+
+#[derive(Accounts)]
+pub struct SetFixingDisputeSettings<'info> {
+    pub authority: Signer<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, seeds=[mint.key().as_ref()], bump=structured_product.bump)]
+    pub structured_product: Account<'info, StructuredProductConfig>,
+}
+
 #[derive(Accounts)]
 pub struct PayIssuance<'info> {
     #[account(mut)]
@@ -825,7 +1927,7 @@ pub struct PayIssuance<'info> {
     associated_token::mint=payment_mint, associated_token::authority=structured_product,
     payer=payer)]
     pub structured_product_token_account: InterfaceAccount<'info, TokenAccount>,
-    pub token_program: Program<'info, Token2022>,
+    pub token_program: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
 }
@@ -851,6 +1953,7 @@ pub struct Issue<'info> {
     pub investor: Signer<'info>,
     #[account(mut)]
     pub issuer: Signer<'info>,
+    pub legal_approver: Option<Signer<'info>>,
     #[account(mut, mint::authority=structured_product, mint::decimals=0)]
     pub mint: InterfaceAccount<'info, Mint>,
     #[account(mut, seeds=[mint.key().as_ref()], bump=structured_product.bump)]
@@ -870,6 +1973,16 @@ pub struct Issue<'info> {
     /// CHECK: account initialized by snapshot hook program
     #[account(mut)]
     pub investor_token_snapshot_balances_account: AccountInfo<'info>,
+    // Only needed to reserve static payments in the issuer treasury wallet
+    pub treasury_wallet: Option<Account<'info, TreasuryWalletAccount>>,
+    /// CHECK: account checked by treasury wallet program
+    #[account(mut)]
+    pub withdrawal_authorization: Option<AccountInfo<'info>>,
+    pub reservation_mint: Option<InterfaceAccount<'info, Mint>>,
+    /// CHECK: account checked by treasury wallet program
+    #[account(mut)]
+    pub mint_ledger: Option<AccountInfo<'info>>,
+    pub treasury_wallet_program: Option<Program<'info, TreasuryWallet>>,
     pub snapshot_transfer_hook_program: Program<'info, TransferSnapshotHook>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub token_program: Program<'info, Token2022>,
@@ -877,6 +1990,161 @@ pub struct Issue<'info> {
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct AddSubscriber<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    /// CHECK: whitelisted investor, only the key is stored
+    pub investor: AccountInfo<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(seeds=[mint.key().as_ref()], bump=structured_product.bump)]
+    pub structured_product: Account<'info, StructuredProductConfig>,
+    #[account(init,
+    seeds=[b"subscription", structured_product.key().as_ref(), investor.key().as_ref()],
+    bump,
+    payer=authority,
+    space=Subscription::space())]
+    pub subscription: Account<'info, Subscription>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Subscribe<'info> {
+    #[account(mut)]
+    pub investor: Signer<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, seeds=[mint.key().as_ref()], bump=structured_product.bump)]
+    pub structured_product: Account<'info, StructuredProductConfig>,
+    #[account(mut,
+    seeds=[b"subscription", structured_product.key().as_ref(), investor.key().as_ref()],
+    bump=subscription.bump)]
+    pub subscription: Account<'info, Subscription>,
+    #[account(mut, token::mint=payment_mint, token::authority=investor)]
+    pub investor_payment_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(address=structured_product.issuance_payment_mint)]
+    pub payment_mint: InterfaceAccount<'info, Mint>,
+    #[account(init_if_needed,
+    associated_token::mint=payment_mint, associated_token::authority=structured_product,
+    payer=investor)]
+    pub structured_product_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseSubscriptions<'info> {
+    #[account(mut)]
+    pub issuer: Signer<'info>,
+    pub legal_approver: Option<Signer<'info>>,
+    #[account(mut, mint::authority=structured_product, mint::decimals=0)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, seeds=[mint.key().as_ref()], bump=structured_product.bump)]
+    pub structured_product: Account<'info, StructuredProductConfig>,
+    /// CHECK: account checked by snapshot hook program
+    #[account(mut)]
+    pub snapshot_config: AccountInfo<'info>,
+    #[account(init, associated_token::authority=structured_product, associated_token::mint=mint, payer=issuer)]
+    pub program_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: account initialized by snapshot hook program
+    #[account(mut)]
+    pub program_token_snapshot_balances_account: AccountInfo<'info>,
+    pub snapshot_transfer_hook_program: Program<'info, TransferSnapshotHook>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub token_program: Program<'info, Token2022>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct IssueSubscription<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    /// CHECK: address checked against the subscription
+    #[account(address=subscription.investor)]
+    pub investor: AccountInfo<'info>,
+    #[account(mut, mint::authority=structured_product, mint::decimals=0)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(seeds=[mint.key().as_ref()], bump=structured_product.bump)]
+    pub structured_product: Account<'info, StructuredProductConfig>,
+    #[account(mut,
+    seeds=[b"subscription", structured_product.key().as_ref(), investor.key().as_ref()],
+    bump=subscription.bump)]
+    pub subscription: Account<'info, Subscription>,
+    /// CHECK: account checked by snapshot hook program
+    pub snapshot_config: AccountInfo<'info>,
+    /// CHECK: account checked token program?
+    pub extra_account_meta_list: AccountInfo<'info>,
+    #[account(mut, associated_token::authority=structured_product, associated_token::mint=mint, associated_token::token_program=token_program)]
+    pub program_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: account checked by snapshot hook program
+    #[account(mut)]
+    pub program_token_snapshot_balances_account: AccountInfo<'info>,
+    #[account(init, associated_token::authority=investor, associated_token::mint=mint, payer=payer)]
+    pub investor_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: account initialized by snapshot hook program
+    #[account(mut)]
+    pub investor_token_snapshot_balances_account: AccountInfo<'info>,
+    #[account(address=structured_product.issuance_payment_mint)]
+    pub payment_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, token::mint=payment_mint, token::authority=investor)]
+    pub investor_payment_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, associated_token::mint=payment_mint, associated_token::authority=structured_product, associated_token::token_program=payment_token_program)]
+    pub structured_product_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub snapshot_transfer_hook_program: Program<'info, TransferSnapshotHook>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub token_program: Program<'info, Token2022>,
+    pub payment_token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelIssuance<'info> {
+    pub issuer: Option<Signer<'info>>,
+    pub investor: Option<Signer<'info>>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, seeds=[mint.key().as_ref()], bump=structured_product.bump)]
+    pub structured_product: Account<'info, StructuredProductConfig>,
+}
+
+#[derive(Accounts)]
+pub struct RefundIssuance<'info> {
+    pub payer: Signer<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, seeds=[mint.key().as_ref()], bump=structured_product.bump)]
+    pub structured_product: Account<'info, StructuredProductConfig>,
+    #[account(address=structured_product.issuance_payment_mint)]
+    pub payment_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut,
+    token::mint=payment_mint,
+    constraint=Some(payer_token_account.owner)==structured_product.issuance_payer @ StructuredProductError::InvalidOwner)]
+    pub payer_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, associated_token::mint=payment_mint, associated_token::authority=structured_product, associated_token::token_program=token_program)]
+    pub structured_product_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct RefundSubscription<'info> {
+    pub payer: Signer<'info>,
+    /// CHECK: address checked against the subscription
+    #[account(address=subscription.investor)]
+    pub investor: AccountInfo<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, seeds=[mint.key().as_ref()], bump=structured_product.bump)]
+    pub structured_product: Account<'info, StructuredProductConfig>,
+    #[account(mut,
+    seeds=[b"subscription", structured_product.key().as_ref(), investor.key().as_ref()],
+    bump=subscription.bump)]
+    pub subscription: Account<'info, Subscription>,
+    #[account(address=structured_product.issuance_payment_mint)]
+    pub payment_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, token::mint=payment_mint, token::authority=investor)]
+    pub investor_payment_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, associated_token::mint=payment_mint, associated_token::authority=structured_product, associated_token::token_program=token_program)]
+    pub structured_product_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
 #[derive(Accounts)]
 pub struct WithdrawIssuanceProceeds<'info> {
     #[account(mut)]
@@ -891,7 +2159,43 @@ pub struct WithdrawIssuanceProceeds<'info> {
     pub payment_mint: InterfaceAccount<'info, Mint>,
     #[account(mut, token::mint=payment_mint, token::authority=structured_product)]
     pub structured_product_token_account: InterfaceAccount<'info, TokenAccount>,
-    pub token_program: Program<'info, Token2022>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(call_date_offset: i64)]
+pub struct AddCallDate<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(seeds=[mint.key().as_ref()], bump=structured_product.bump)]
+    pub structured_product: Account<'info, StructuredProductConfig>,
+    #[account(seeds=[b"snapshots", mint.key().as_ref()], seeds::program=snapshot_transfer_hook_program, bump)]
+    pub snapshot_config: Account<'info, SnapshotConfig>,
+    #[account(init,
+    seeds=[b"call", structured_product.key().as_ref(), &call_date_offset.to_le_bytes()],
+    bump,
+    payer=authority,
+    space=CallDate::space())]
+    pub call_date: Account<'info, CallDate>,
+    pub snapshot_transfer_hook_program: Program<'info, TransferSnapshotHook>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(call_date_offset: i64, maturity_date_offset: i64)]
+pub struct ExerciseCall<'info> {
+    pub issuer: Signer<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, seeds=[mint.key().as_ref()], bump=structured_product.bump)]
+    pub structured_product: Account<'info, StructuredProductConfig>,
+    #[account(mut, seeds=[b"snapshots", mint.key().as_ref()], seeds::program=snapshot_transfer_hook_program, bump)]
+    pub snapshot_config: Account<'info, SnapshotConfig>,
+    #[account(seeds=[b"call", structured_product.key().as_ref(), &call_date_offset.to_le_bytes()], bump=call_date.bump)]
+    pub call_date: Account<'info, CallDate>,
+    #[account(mut, seeds=[structured_product.key().as_ref(), &[true.into()], &maturity_date_offset.to_le_bytes()], bump=principal_payment.bump)]
+    pub principal_payment: Account<'info, Payment>,
+    pub snapshot_transfer_hook_program: Program<'info, TransferSnapshotHook>,
 }
 
 #[derive(Accounts)]
@@ -905,12 +2209,39 @@ pub struct SetPaymentPrice<'info> {
     payment: Account<'info, Payment>,
 }
 
+#[derive(Accounts)]
+#[instruction(_payment_date_offset: i64)]
+pub struct DisputePaymentPrice<'info> {
+    disputer: Signer<'info>,
+    mint: InterfaceAccount<'info, Mint>,
+    #[account(seeds=[mint.key().as_ref()], bump=structured_product.bump)]
+    structured_product: Account<'info, StructuredProductConfig>,
+    #[account(mut, seeds=[structured_product.key().as_ref(), &[payment.principal.into()], &_payment_date_offset.to_le_bytes()], bump=payment.bump)]
+    payment: Account<'info, Payment>,
+    // only needed for holders other than the investor and the issuer, checked in the instruction
+    snapshot_config: Option<Account<'info, SnapshotConfig>>,
+    disputer_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    disputer_snapshot_balances_account: Option<Account<'info, SnapshotTokenAccountBalances>>,
+}
+
+#[derive(Accounts)]
+#[instruction(_payment_date_offset: i64)]
+pub struct CorrectPaymentPrice<'info> {
+    calculation_agent: Signer<'info>,
+    mint: InterfaceAccount<'info, Mint>,
+    #[account(seeds=[mint.key().as_ref()], bump=structured_product.bump)]
+    structured_product: Account<'info, StructuredProductConfig>,
+    #[account(mut, seeds=[structured_product.key().as_ref(), &[payment.principal.into()], &_payment_date_offset.to_le_bytes()], bump=payment.bump)]
+    payment: Account<'info, Payment>,
+}
+
 #[derive(Accounts)]
 #[instruction(_payment_date_offset: i64)]
 pub struct PullPayment<'info> {
     #[account(mut)]
     payer: Signer<'info>,
     /// CHECK: account checked by treasury wallet program
+    #[account(mut)]
     withdrawal_authorization: AccountInfo<'info>,
     #[account()]
     treasury_wallet: Account<'info, TreasuryWalletAccount>,
@@ -918,6 +2249,9 @@ pub struct PullPayment<'info> {
     treasury_authority: AccountInfo<'info>,
     #[account(mut, token::authority=treasury_authority)]
     treasury_wallet_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: account checked by treasury wallet program
+    #[account(mut)]
+    mint_ledger: AccountInfo<'info>,
     mint: InterfaceAccount<'info, Mint>,
     #[account(mut, seeds=[mint.key().as_ref()], bump=structured_product.bump)]
     structured_product: Account<'info, StructuredProductConfig>,
@@ -927,7 +2261,39 @@ pub struct PullPayment<'info> {
     #[account(init, associated_token::authority=payment, associated_token::mint=payment_mint, payer=payer)]
     payment_token_account: InterfaceAccount<'info, TokenAccount>,
     treasury_wallet_program: Program<'info, TreasuryWallet>,
-    token_program: Program<'info, Token2022>,
+    token_program: Interface<'info, TokenInterface>,
+    associated_token_program: Program<'info, AssociatedToken>,
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(_payment_date_offset: i64)]
+pub struct PullDelivery<'info> {
+    #[account(mut)]
+    payer: Signer<'info>,
+    /// CHECK: account checked by treasury wallet program
+    #[account(mut)]
+    withdrawal_authorization: AccountInfo<'info>,
+    #[account()]
+    treasury_wallet: Account<'info, TreasuryWalletAccount>,
+    ///CHECK: account will be checked by treasury wallet program
+    treasury_authority: AccountInfo<'info>,
+    #[account(mut, token::authority=treasury_authority)]
+    treasury_wallet_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: account checked by treasury wallet program
+    #[account(mut)]
+    mint_ledger: AccountInfo<'info>,
+    mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, seeds=[mint.key().as_ref()], bump=structured_product.bump)]
+    structured_product: Account<'info, StructuredProductConfig>,
+    delivery_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, seeds=[structured_product.key().as_ref(), &[payment.principal.into()], &_payment_date_offset.to_le_bytes()], bump=payment.bump)]
+    payment: Account<'info, Payment>,
+    #[account(init, associated_token::authority=payment, associated_token::mint=delivery_mint, associated_token::token_program=delivery_token_program, payer=payer)]
+    payment_delivery_token_account: InterfaceAccount<'info, TokenAccount>,
+    treasury_wallet_program: Program<'info, TreasuryWallet>,
+    // the delivery mint can be a Token-2022 or legacy SPL Token mint
+    delivery_token_program: Interface<'info, TokenInterface>,
     associated_token_program: Program<'info, AssociatedToken>,
     system_program: Program<'info, System>,
 }
@@ -958,8 +2324,15 @@ pub struct SettlePayment<'info> {
     #[account(mut, token::mint=payment_mint, token::authority=beneficiary)]
     beneficiary_payment_token_account: InterfaceAccount<'info, TokenAccount>,
     snapshot_transfer_hook_program: Program<'info, TransferSnapshotHook>,
-    token_program: Program<'info, Token2022>,
+    token_program: Interface<'info, TokenInterface>,
     system_program: Program<'info, System>,
+    delivery_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut)]
+    payment_delivery_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut)]
+    beneficiary_delivery_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    // the delivery mint does not have to use the token program of the payment mint
+    delivery_token_program: Option<Interface<'info, TokenInterface>>,
 }
 
 #[account]
@@ -975,9 +2348,82 @@ pub struct StructuredProductConfig {
     num_payments: u8,
     principal_defined: bool,
     issuance_date: Option<i64>,
+    subscriptions_closed: bool,
+    total_subscribed: u64,
+    // subscribed units of the subscriptions issued so far
+    total_issued: u64,
+    offer_expiry: Option<i64>,
+    issuance_payer: Option<Pubkey>,
+    cancelled: bool,
+    legal_approver: Option<Pubkey>,
+    call_date_offset: Option<i64>,
+    dispute_window: i64,
+    calculation_agent: Option<Pubkey>,
     bump: u8,
 }
 
+impl StructuredProductConfig {
+    pub fn space() -> usize {
+        8 + 32
+            + 32
+            + 32
+            + 8
+            + 32
+            + 32
+            + 8
+            + 1
+            + 1
+            + 1
+            + 9
+            + 1
+            + 8
+            + 8
+            + 9
+            + 33
+            + 1
+            + 33
+            + 9
+            + 8
+            + 33
+            + 1
+    }
+
+    pub fn issuance_date(&self) -> Option<i64> {
+        self.issuance_date
+    }
+
+    // Signed offline offers must not be broadcast after they expired
+    pub fn check_offer_not_expired(&self, timestamp: i64) -> Result<()> {
+        if let Some(offer_expiry) = self.offer_expiry {
+            require!(
+                timestamp <= offer_expiry,
+                StructuredProductError::OfferExpired
+            );
+        }
+        Ok(())
+    }
+
+    // The supply is only fully minted once every subscription of the book is issued
+    pub fn check_book_issued(&self) -> Result<()> {
+        require!(
+            self.total_issued == self.total_subscribed,
+            StructuredProductError::BookNotIssued
+        );
+        Ok(())
+    }
+
+    // If a legal approver is configured, it has to co-sign the issuance
+    pub fn check_legal_approval(&self, legal_approver: &Option<Signer>) -> Result<()> {
+        if let Some(expected) = self.legal_approver {
+            require!(
+                matches!(legal_approver, Some(signer) if signer.key() == expected),
+                StructuredProductError::LegalApprovalMissing
+            );
+        }
+        Ok(())
+    }
+}
+
 #[account]
 pub struct Payment {
     pub payment_mint: Pubkey,
@@ -985,10 +2431,171 @@ pub struct Payment {
     pub price_per_unit: Option<u64>,
     pub principal: bool,
     pub paid: bool,
+    pub cancelled: bool,
+    pub early_redemption_date_offset: Option<i64>,
+    pub delivery_mint: Option<Pubkey>,
+    pub delivery_per_unit: Option<u64>,
+    pub fixing_status: FixingStatus,
+    pub bump: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FixingStatus {
+    Final,
+    Proposed { proposed_at: i64 },
+    Disputed,
+}
+
+impl FixingStatus {
+    // Without a dispute window fixings are final as soon as they are set
+    pub fn proposed(dispute_window: i64, current_time: i64) -> Self {
+        if dispute_window == 0 {
+            FixingStatus::Final
+        } else {
+            FixingStatus::Proposed {
+                proposed_at: current_time,
+            }
+        }
+    }
+
+    pub fn is_final(&self, dispute_window: i64, current_time: i64) -> bool {
+        match self {
+            FixingStatus::Final => true,
+            FixingStatus::Proposed { proposed_at } => {
+                current_time >= proposed_at.saturating_add(dispute_window)
+            }
+            FixingStatus::Disputed => false,
+        }
+    }
+}
+
+impl Payment {
+    pub fn check_price_settable(
+        &self,
+        authority: &Pubkey,
+        issuance_date: Option<i64>,
+        payment_date_offset: i64,
+        current_time: i64,
+    ) -> Result<()> {
+        require!(
+            Some(*authority) == self.price_authority,
+            StructuredProductError::Unauthorized
+        );
+        // payment dates are offsets to the issuance date
+        let issuance_date = issuance_date.ok_or(StructuredProductError::NotIssued)?;
+        require!(
+            issuance_date.saturating_add(payment_date_offset) <= current_time,
+            StructuredProductError::DateNotInPast
+        );
+        require!(
+            self.price_per_unit.is_none(),
+            StructuredProductError::PaymentAmountAlreadySet
+        );
+        require!(!self.cancelled, StructuredProductError::PaymentCancelled);
+        Ok(())
+    }
+
+    pub fn check_fixing_final(&self, dispute_window: i64, current_time: i64) -> Result<()> {
+        require!(
+            self.fixing_status.is_final(dispute_window, current_time),
+            StructuredProductError::FixingNotFinal
+        );
+        Ok(())
+    }
+}
+
+#[account]
+pub struct CallDate {
+    pub call_price_per_unit: u64,
     pub bump: u8,
 }
 
+impl CallDate {
+    pub fn space() -> usize {
+        8 + 8 + 1
+    }
+}
+
 #[account]
 pub struct PaymentPaid {
     paid: bool,
 }
+
+#[account]
+pub struct Subscription {
+    pub investor: Pubkey,
+    pub units: u64,
+    pub allocation: Option<u64>,
+    pub bump: u8,
+}
+
+impl Subscription {
+    pub fn space() -> usize {
+        8 + 32 + 8 + 9 + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! calc_allocation_tests {
+        ($($name:ident: $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (units, total_subscribed, supply, expected) = $expected;
+                    let result = calc_allocation(units, total_subscribed, supply);
+                    assert_eq!(result, expected);
+                }
+            )*
+        }
+    }
+
+    #[cfg(test)]
+    calc_allocation_tests! {
+        calc_allocation_test_1: (100, 100, 100, 100,),
+        calc_allocation_test_2: (40, 80, 100, 40,),
+        calc_allocation_test_3: (100, 200, 100, 50,),
+        calc_allocation_test_4: (1, 3, 2, 0,),
+        calc_allocation_test_5: (2, 3, 2, 1,),
+        calc_allocation_test_6: (333, 1000, 100, 33,),
+        calc_allocation_test_7: (0, 200, 100, 0,),
+        calc_allocation_test_8: (u64::MAX, u64::MAX, 1000, 1000,),
+    }
+
+    macro_rules! fixing_status_is_final_tests {
+        ($($name:ident: $expected:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (fixing_status, dispute_window, current_time, expected) = $expected;
+                    let result = FixingStatus::is_final(&fixing_status, dispute_window, current_time);
+                    assert_eq!(result, expected);
+                }
+            )*
+        }
+    }
+
+    #[cfg(test)]
+    fixing_status_is_final_tests! {
+        fixing_status_is_final_test_1: (FixingStatus::Final, 0, 0, true,),
+        fixing_status_is_final_test_2: (FixingStatus::Final, 86400, 0, true,),
+        fixing_status_is_final_test_3: (FixingStatus::Proposed { proposed_at: 1000 }, 86400, 1000, false,),
+        fixing_status_is_final_test_4: (FixingStatus::Proposed { proposed_at: 1000 }, 86400, 87399, false,),
+        fixing_status_is_final_test_5: (FixingStatus::Proposed { proposed_at: 1000 }, 86400, 87400, true,),
+        fixing_status_is_final_test_6: (FixingStatus::Proposed { proposed_at: 1000 }, 0, 1000, true,),
+        fixing_status_is_final_test_7: (FixingStatus::Proposed { proposed_at: i64::MAX }, 86400, i64::MAX, true,),
+        fixing_status_is_final_test_8: (FixingStatus::Disputed, 86400, i64::MAX, false,),
+        fixing_status_is_final_test_9: (FixingStatus::Disputed, 0, 0, false,),
+    }
+
+    #[test]
+    fn fixing_status_proposed_without_window_is_final() {
+        assert_eq!(FixingStatus::proposed(0, 1000), FixingStatus::Final);
+        assert_eq!(
+            FixingStatus::proposed(3600, 1000),
+            FixingStatus::Proposed { proposed_at: 1000 }
+        );
+    }
+}
@@ -100,6 +100,34 @@ pub mod transfer_snapshot_hook {
         Ok(())
     }
 
+    // drops all snapshots after the given one, e.g. when a product is redeemed early
+    pub fn truncate_snapshots(
+        ctx: Context<TruncateSnapshots>,
+        timestamp_offset: i64,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.snapshot_config.authority,
+            SnapshotHookError::Unauthorized
+        );
+
+        let defined_snapshots = ctx.accounts.snapshot_config.defined_snapshots as usize;
+
+        let snapshot_index = ctx.accounts.snapshot_config.snapshots[..defined_snapshots]
+            .iter()
+            .position(|&snapshot| snapshot == timestamp_offset);
+
+        require!(snapshot_index.is_some(), SnapshotHookError::NoSnapshotFound);
+
+        let snapshot_index = snapshot_index.unwrap();
+
+        let snapshot_config = &mut ctx.accounts.snapshot_config;
+        for snapshot in snapshot_config.snapshots[snapshot_index + 1..].iter_mut() {
+            *snapshot = 0;
+        }
+        snapshot_config.defined_snapshots = (snapshot_index + 1) as u8;
+        Ok(())
+    }
+
     pub fn activate(ctx: Context<ActivateSnapshots>) -> Result<()> {
         require!(
             ctx.accounts.authority.key() == ctx.accounts.snapshot_config.authority,
@@ -275,6 +303,13 @@ pub struct DefineSnapshot<'info> {
     pub authority: Signer<'info>,
 }
 
+#[derive(Accounts)]
+pub struct TruncateSnapshots<'info> {
+    #[account(mut)]
+    pub snapshot_config: Account<'info, SnapshotConfig>,
+    pub authority: Signer<'info>,
+}
+
 #[derive(Accounts)]
 pub struct ActivateSnapshots<'info> {
     #[account(mut)]
@@ -1,5 +1,5 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token_interface::{Mint, Token2022, TokenAccount};
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
 
 declare_id!("3DUqJ4S1dUoKzC77NmJXq2wiDqwR3NoNkEwtkFU4SaY3");
 #[error_code]
@@ -8,36 +8,458 @@ pub enum TreasuryWalletError {
     InvalidOwner,
     #[msg("Unauthorized")]
     Unauthorized,
+    #[msg("Invalid withdrawal limits")]
+    InvalidWithdrawalLimits,
+    #[msg("Withdrawal limit exceeded")]
+    WithdrawalLimitExceeded,
+    #[msg("Authorization expired")]
+    AuthorizationExpired,
+    #[msg("No pending owner")]
+    NoPendingOwner,
+    #[msg("Invalid multisig")]
+    InvalidMultisig,
+    #[msg("Invalid proposal")]
+    InvalidProposal,
+    #[msg("Proposal expired")]
+    ProposalExpired,
+    #[msg("Proposal already executed")]
+    ProposalAlreadyExecuted,
+    #[msg("Already approved")]
+    AlreadyApproved,
+    #[msg("Approval threshold not met")]
+    ThresholdNotMet,
+    #[msg("Insufficient free balance")]
+    InsufficientFreeBalance,
+    #[msg("Too many reservations")]
+    TooManyReservations,
+    #[msg("Outstanding reservations")]
+    OutstandingReservations,
+    #[msg("Destination not allowed")]
+    DestinationNotAllowed,
 }
 
+pub const MAX_MINT_LIMITS: usize = 8;
+pub const MAX_SIGNERS: usize = 10;
+pub const MAX_RESERVATIONS: usize = 32;
+pub const MAX_ALLOWED_DESTINATIONS: usize = 4;
+
 #[program]
 pub mod treasury_wallet {
-    use anchor_spl::token_2022;
+    use anchor_spl::token_interface;
 
     use super::*;
 
     pub fn initialize(ctx: Context<Initialize>) -> Result<()> {
         let treasury_wallet = &mut ctx.accounts.treasury_wallet;
         treasury_wallet.owner = ctx.accounts.owner.key();
+        treasury_wallet.pending_owner = None;
+        treasury_wallet.signers = Vec::new();
+        treasury_wallet.threshold = 0;
+        treasury_wallet.proposal_count = 0;
+        Ok(())
+    }
+
+    // Once a multisig is set, owner actions need a proposal approved by threshold of the signers,
+    // an empty signer set with threshold 0 returns control to the owner
+    pub fn set_multisig(
+        ctx: Context<SetMultisig>,
+        signers: Vec<Pubkey>,
+        threshold: u8,
+    ) -> Result<()> {
+        let action = OwnerAction::SetMultisig {
+            signers: signers.clone(),
+            threshold,
+        };
+        let treasury_wallet_key = ctx.accounts.treasury_wallet.key();
+        ctx.accounts.treasury_wallet.authorize_owner_action(
+            treasury_wallet_key,
+            ctx.accounts.owner.key,
+            ctx.accounts.proposal.as_deref_mut(),
+            &action,
+            Clock::get()?.unix_timestamp,
+        )?;
+        require!(
+            signers.len() <= MAX_SIGNERS
+                && (threshold as usize) <= signers.len()
+                && (threshold == 0) == signers.is_empty()
+                && signers
+                    .iter()
+                    .enumerate()
+                    .all(|(i, signer)| !signers[..i].contains(signer)),
+            TreasuryWalletError::InvalidMultisig
+        );
+
+        let treasury_wallet = &mut ctx.accounts.treasury_wallet;
+        treasury_wallet.signers = signers;
+        treasury_wallet.threshold = threshold;
+        Ok(())
+    }
+
+    // Proposals can only be created by signers of the multisig, the proposer approves right away
+    pub fn create_proposal(
+        ctx: Context<CreateProposal>,
+        action: OwnerAction,
+        expires_at: i64,
+    ) -> Result<()> {
+        let treasury_wallet = &ctx.accounts.treasury_wallet;
+        require!(
+            treasury_wallet.threshold > 0
+                && treasury_wallet.signers.contains(ctx.accounts.proposer.key),
+            TreasuryWalletError::Unauthorized
+        );
+        require!(
+            expires_at > Clock::get()?.unix_timestamp,
+            TreasuryWalletError::ProposalExpired
+        );
+
+        msg!(
+            "Proposal {} at {}: {:?}",
+            treasury_wallet.proposal_count,
+            ctx.accounts.proposal.key(),
+            action
+        );
+
+        let proposal = &mut ctx.accounts.proposal;
+        proposal.treasury_wallet = ctx.accounts.treasury_wallet.key();
+        proposal.index = ctx.accounts.treasury_wallet.proposal_count;
+        proposal.action = action;
+        proposal.approvals = vec![ctx.accounts.proposer.key()];
+        proposal.expires_at = expires_at;
+        proposal.executed = false;
+        proposal.bump = ctx.bumps.proposal;
+
+        let treasury_wallet = &mut ctx.accounts.treasury_wallet;
+        treasury_wallet.proposal_count += 1;
+        Ok(())
+    }
+
+    pub fn approve_proposal(ctx: Context<ApproveProposal>) -> Result<()> {
+        require!(
+            ctx.accounts
+                .treasury_wallet
+                .signers
+                .contains(ctx.accounts.signer.key),
+            TreasuryWalletError::Unauthorized
+        );
+        ctx.accounts
+            .proposal
+            .approve(ctx.accounts.signer.key(), Clock::get()?.unix_timestamp)
+    }
+
+    // Ownership is only transferred once the new owner accepts it,
+    // proposing None cancels a pending transfer
+    pub fn propose_owner(ctx: Context<ProposeOwner>, new_owner: Option<Pubkey>) -> Result<()> {
+        let treasury_wallet_key = ctx.accounts.treasury_wallet.key();
+        ctx.accounts.treasury_wallet.authorize_owner_action(
+            treasury_wallet_key,
+            ctx.accounts.owner.key,
+            ctx.accounts.proposal.as_deref_mut(),
+            &OwnerAction::ProposeOwner { new_owner },
+            Clock::get()?.unix_timestamp,
+        )?;
+
+        let treasury_wallet = &mut ctx.accounts.treasury_wallet;
+        treasury_wallet.pending_owner = new_owner;
         Ok(())
     }
 
-    pub fn add_withdraw_authorization(ctx: Context<AddWithdrawAuthorization>) -> Result<()> {
+    pub fn accept_owner(ctx: Context<AcceptOwner>) -> Result<()> {
+        let pending_owner = ctx
+            .accounts
+            .treasury_wallet
+            .pending_owner
+            .ok_or(TreasuryWalletError::NoPendingOwner)?;
         require!(
-            ctx.accounts.treasury_wallet.owner == ctx.accounts.owner.key(),
+            pending_owner == ctx.accounts.new_owner.key(),
             TreasuryWalletError::Unauthorized
         );
+
+        let treasury_wallet = &mut ctx.accounts.treasury_wallet;
+        treasury_wallet.owner = pending_owner;
+        treasury_wallet.pending_owner = None;
+        Ok(())
+    }
+
+    pub fn add_withdraw_authorization(
+        ctx: Context<AddWithdrawAuthorization>,
+        limits: WithdrawalLimits,
+        valid_until: Option<i64>,
+        allowed_destinations: Vec<DestinationConstraint>,
+    ) -> Result<()> {
+        let action = OwnerAction::AddWithdrawAuthorization {
+            authority: ctx.accounts.authority.key(),
+            limits: limits.clone(),
+            valid_until,
+            allowed_destinations: allowed_destinations.clone(),
+        };
+        let treasury_wallet_key = ctx.accounts.treasury_wallet.key();
+        ctx.accounts.treasury_wallet.authorize_owner_action(
+            treasury_wallet_key,
+            ctx.accounts.owner.key,
+            ctx.accounts.proposal.as_deref_mut(),
+            &action,
+            Clock::get()?.unix_timestamp,
+        )?;
+        limits.validate()?;
+        require!(
+            allowed_destinations.len() <= MAX_ALLOWED_DESTINATIONS,
+            TreasuryWalletError::InvalidWithdrawalLimits
+        );
+
         let withdraw_authorization = &mut ctx.accounts.withdraw_authorization;
         withdraw_authorization.authority = ctx.accounts.authority.key();
+        withdraw_authorization.limits = limits;
+        withdraw_authorization.valid_until = valid_until;
+        withdraw_authorization.allowed_destinations = allowed_destinations;
+        withdraw_authorization.total_withdrawn = 0;
+        withdraw_authorization.period_start = 0;
+        withdraw_authorization.period_withdrawn = 0;
+        withdraw_authorization.mint_withdrawals = Vec::new();
+        withdraw_authorization.reservations = Vec::new();
         Ok(())
     }
 
+    // Replaces the limits, the amounts withdrawn so far keep counting against the new limits
+    pub fn set_withdrawal_limits(
+        ctx: Context<SetWithdrawalLimits>,
+        limits: WithdrawalLimits,
+    ) -> Result<()> {
+        let action = OwnerAction::SetWithdrawalLimits {
+            authority: ctx.accounts.withdraw_authorization.authority,
+            limits: limits.clone(),
+        };
+        let treasury_wallet_key = ctx.accounts.treasury_wallet.key();
+        ctx.accounts.treasury_wallet.authorize_owner_action(
+            treasury_wallet_key,
+            ctx.accounts.owner.key,
+            ctx.accounts.proposal.as_deref_mut(),
+            &action,
+            Clock::get()?.unix_timestamp,
+        )?;
+        limits.validate()?;
+
+        ctx.accounts.withdraw_authorization.set_limits(limits);
+        Ok(())
+    }
+
+    // An empty list allows withdrawals to any destination
+    pub fn set_allowed_destinations(
+        ctx: Context<SetWithdrawalLimits>,
+        allowed_destinations: Vec<DestinationConstraint>,
+    ) -> Result<()> {
+        let action = OwnerAction::SetAllowedDestinations {
+            authority: ctx.accounts.withdraw_authorization.authority,
+            allowed_destinations: allowed_destinations.clone(),
+        };
+        let treasury_wallet_key = ctx.accounts.treasury_wallet.key();
+        ctx.accounts.treasury_wallet.authorize_owner_action(
+            treasury_wallet_key,
+            ctx.accounts.owner.key,
+            ctx.accounts.proposal.as_deref_mut(),
+            &action,
+            Clock::get()?.unix_timestamp,
+        )?;
+        require!(
+            allowed_destinations.len() <= MAX_ALLOWED_DESTINATIONS,
+            TreasuryWalletError::InvalidWithdrawalLimits
+        );
+
+        let withdraw_authorization = &mut ctx.accounts.withdraw_authorization;
+        withdraw_authorization.allowed_destinations = allowed_destinations;
+        Ok(())
+    }
+
+    // Closes the authorization, the rent is returned to the owner
+    // Authorizations with outstanding reservations cannot be revoked, the reserved funds
+    // stay committed until the reserved payments are pulled
+    pub fn revoke_withdraw_authorization(ctx: Context<RevokeWithdrawAuthorization>) -> Result<()> {
+        let action = OwnerAction::RevokeWithdrawAuthorization {
+            authority: ctx.accounts.withdraw_authorization.authority,
+        };
+        let treasury_wallet_key = ctx.accounts.treasury_wallet.key();
+        ctx.accounts.treasury_wallet.authorize_owner_action(
+            treasury_wallet_key,
+            ctx.accounts.owner.key,
+            ctx.accounts.proposal.as_deref_mut(),
+            &action,
+            Clock::get()?.unix_timestamp,
+        )?;
+
+        ctx.accounts.withdraw_authorization.check_revocable()?;
+
+        msg!(
+            "Revoked withdraw authorization of {}",
+            ctx.accounts.withdraw_authorization.authority
+        );
+        Ok(())
+    }
+
+    // Anyone can fund the treasury, deposits work for Token-2022 and legacy SPL mints
+    pub fn deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
+        let mint_ledger = &mut ctx.accounts.mint_ledger;
+        mint_ledger.treasury_wallet = ctx.accounts.treasury_wallet.key();
+        mint_ledger.mint = ctx.accounts.mint.key();
+        mint_ledger.bump = ctx.bumps.mint_ledger;
+
+        let cpi_accounts = token_interface::TransferChecked {
+            from: ctx.accounts.source.to_account_info(),
+            to: ctx.accounts.treasury_wallet_token_account.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            authority: ctx.accounts.depositor.to_account_info(),
+        };
+
+        token_interface::transfer_checked(
+            CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts),
+            amount,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        Ok(())
+    }
+
+    // The owner can only withdraw the free balance, reserved funds stay for the products
+    pub fn owner_withdraw(ctx: Context<OwnerWithdraw>, amount: u64) -> Result<()> {
+        let action = OwnerAction::OwnerWithdraw {
+            mint: ctx.accounts.mint.key(),
+            amount,
+            destination: ctx.accounts.destination.key(),
+        };
+        let treasury_wallet_key = ctx.accounts.treasury_wallet.key();
+        ctx.accounts.treasury_wallet.authorize_owner_action(
+            treasury_wallet_key,
+            ctx.accounts.owner.key,
+            ctx.accounts.proposal.as_deref_mut(),
+            &action,
+            Clock::get()?.unix_timestamp,
+        )?;
+        require!(
+            amount
+                <= ctx
+                    .accounts
+                    .mint_ledger
+                    .free_balance(ctx.accounts.treasury_wallet_token_account.amount),
+            TreasuryWalletError::InsufficientFreeBalance
+        );
+
+        let authorization_signer_seeds = &[
+            treasury_wallet_key.as_ref(),
+            &[ctx.bumps.treasury_authority],
+        ];
+
+        let cpi_accounts = token_interface::TransferChecked {
+            from: ctx.accounts.treasury_wallet_token_account.to_account_info(),
+            to: ctx.accounts.destination.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            authority: ctx.accounts.treasury_authority.to_account_info(),
+        };
+
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                cpi_accounts,
+                &[&authorization_signer_seeds[..]],
+            ),
+            amount,
+            ctx.accounts.mint.decimals,
+        )?;
+
+        Ok(())
+    }
+
+    // Earmarks funds of the treasury for a payment of the authority's product
+    pub fn reserve(ctx: Context<Reserve>, payment_date_offset: i64, amount: u64) -> Result<()> {
+        require!(
+            ctx.accounts.withdraw_authorization.authority == ctx.accounts.authority.key(),
+            TreasuryWalletError::Unauthorized
+        );
+        ctx.accounts
+            .withdraw_authorization
+            .check_valid(Clock::get()?.unix_timestamp)?;
+
+        ctx.accounts.withdraw_authorization.add_reservation(
+            ctx.accounts.mint.key(),
+            payment_date_offset,
+            amount,
+        )?;
+
+        let mint_ledger = &mut ctx.accounts.mint_ledger;
+        mint_ledger.treasury_wallet = ctx.accounts.treasury_wallet.key();
+        mint_ledger.mint = ctx.accounts.mint.key();
+        mint_ledger.reserved = mint_ledger
+            .reserved
+            .checked_add(amount)
+            .ok_or(TreasuryWalletError::TooManyReservations)?;
+        mint_ledger.bump = ctx.bumps.mint_ledger;
+
+        msg!(
+            "Reserved {} for payment date {}, total reserved: {}",
+            amount,
+            payment_date_offset,
+            mint_ledger.reserved
+        );
+        Ok(())
+    }
+
+    pub fn get_coverage(ctx: Context<GetCoverage>) -> Result<Coverage> {
+        let coverage = ctx
+            .accounts
+            .mint_ledger
+            .coverage(ctx.accounts.treasury_wallet_token_account.amount);
+        msg!("Coverage: {:?}", coverage);
+        Ok(coverage)
+    }
+
+    // Withdrawals consume the reservations of the authority first,
+    // the rest has to be covered by the free balance
     pub fn withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
         require!(
             ctx.accounts.withdraw_authorization.authority == ctx.accounts.authority.key(),
             TreasuryWalletError::Unauthorized
         );
 
+        let current_time = Clock::get()?.unix_timestamp;
+        ctx.accounts
+            .withdraw_authorization
+            .check_valid(current_time)?;
+        let destination_owner_program = ctx
+            .accounts
+            .destination_owner
+            .as_ref()
+            .filter(|destination_owner| destination_owner.key() == ctx.accounts.destination.owner)
+            .map(|destination_owner| *destination_owner.owner);
+        ctx.accounts.withdraw_authorization.check_destination(
+            &ctx.accounts.destination.key(),
+            &ctx.accounts.destination.owner,
+            destination_owner_program.as_ref(),
+        )?;
+        ctx.accounts.withdraw_authorization.record_withdrawal(
+            ctx.accounts.mint.key(),
+            amount,
+            current_time,
+        )?;
+
+        // Without a ledger nothing was ever reserved for the mint
+        if !ctx.accounts.mint_ledger.data_is_empty() {
+            require!(
+                ctx.accounts.mint_ledger.owner == &crate::ID,
+                TreasuryWalletError::Unauthorized
+            );
+            let mut mint_ledger =
+                MintLedger::try_deserialize(&mut &ctx.accounts.mint_ledger.try_borrow_data()?[..])?;
+            let consumed = ctx
+                .accounts
+                .withdraw_authorization
+                .consume_reservations(ctx.accounts.mint.key(), amount);
+            mint_ledger.reserved = mint_ledger.reserved.saturating_sub(consumed);
+            require!(
+                amount - consumed
+                    <= mint_ledger.free_balance(ctx.accounts.treasury_wallet_token_account.amount),
+                TreasuryWalletError::InsufficientFreeBalance
+            );
+            mint_ledger
+                .try_serialize(&mut &mut ctx.accounts.mint_ledger.try_borrow_mut_data()?[..])?;
+        }
+
         let treasury_wallet_key = ctx.accounts.treasury_wallet.key();
 
         let authorization_signer_seeds = &[
@@ -45,7 +467,7 @@ pub mod treasury_wallet {
             &[ctx.bumps.treasury_authority],
         ];
 
-        let cpi_accounts = token_2022::TransferChecked {
+        let cpi_accounts = token_interface::TransferChecked {
             from: ctx.accounts.treasury_wallet_token_account.to_account_info(),
             to: ctx.accounts.destination.to_account_info(),
             mint: ctx.accounts.mint.to_account_info(),
@@ -54,7 +476,7 @@ pub mod treasury_wallet {
 
         let cpi_program = ctx.accounts.token_program.to_account_info();
 
-        token_2022::transfer_checked(
+        token_interface::transfer_checked(
             CpiContext::new_with_signer(
                 cpi_program,
                 cpi_accounts,
@@ -72,7 +494,7 @@ pub mod treasury_wallet {
 pub struct Initialize<'info> {
     #[account(mut)]
     pub owner: Signer<'info>,
-    #[account(init, payer = owner, space =100)] // TODO: calc proper space
+    #[account(init, payer = owner, space = TreasuryWalletAccount::space())]
     pub treasury_wallet: Account<'info, TreasuryWalletAccount>,
     /// CHECK: account will never be validated, it's just used to sign transactions as the treasury wallet
     #[account(seeds = [treasury_wallet.key().as_ref()], bump)]
@@ -81,6 +503,53 @@ pub struct Initialize<'info> {
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct SetMultisig<'info> {
+    pub owner: Signer<'info>,
+    #[account(mut)]
+    pub treasury_wallet: Account<'info, TreasuryWalletAccount>,
+    #[account(mut)]
+    pub proposal: Option<Account<'info, Proposal>>,
+}
+
+#[derive(Accounts)]
+pub struct CreateProposal<'info> {
+    #[account(mut)]
+    pub proposer: Signer<'info>,
+    #[account(mut)]
+    pub treasury_wallet: Account<'info, TreasuryWalletAccount>,
+    #[account(init,
+    seeds = [b"proposal", treasury_wallet.key().as_ref(), &treasury_wallet.proposal_count.to_le_bytes()],
+    bump, payer = proposer, space = Proposal::space())]
+    pub proposal: Account<'info, Proposal>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveProposal<'info> {
+    pub signer: Signer<'info>,
+    #[account()]
+    pub treasury_wallet: Account<'info, TreasuryWalletAccount>,
+    #[account(mut, has_one = treasury_wallet)]
+    pub proposal: Account<'info, Proposal>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeOwner<'info> {
+    pub owner: Signer<'info>,
+    #[account(mut)]
+    pub treasury_wallet: Account<'info, TreasuryWalletAccount>,
+    #[account(mut)]
+    pub proposal: Option<Account<'info, Proposal>>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptOwner<'info> {
+    pub new_owner: Signer<'info>,
+    #[account(mut)]
+    pub treasury_wallet: Account<'info, TreasuryWalletAccount>,
+}
+
 #[derive(Accounts)]
 pub struct AddWithdrawAuthorization<'info> {
     #[account(mut)]
@@ -91,12 +560,79 @@ pub struct AddWithdrawAuthorization<'info> {
     pub treasury_wallet: Account<'info, TreasuryWalletAccount>,
     #[account(init,
     seeds = [treasury_wallet.key().as_ref(), authority.key().as_ref()],
-    bump, payer = owner, space = 48)]
+    bump, payer = owner, space = WithdrawAuthorization::space())]
     pub withdraw_authorization: Account<'info, WithdrawAuthorization>,
+    #[account(mut)]
+    pub proposal: Option<Account<'info, Proposal>>,
     pub rent: Sysvar<'info, Rent>,
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct SetWithdrawalLimits<'info> {
+    pub owner: Signer<'info>,
+    #[account()]
+    pub treasury_wallet: Account<'info, TreasuryWalletAccount>,
+    #[account(mut, seeds = [treasury_wallet.key().as_ref(), withdraw_authorization.authority.as_ref()], bump)]
+    pub withdraw_authorization: Account<'info, WithdrawAuthorization>,
+    #[account(mut)]
+    pub proposal: Option<Account<'info, Proposal>>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeWithdrawAuthorization<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    #[account()]
+    pub treasury_wallet: Account<'info, TreasuryWalletAccount>,
+    #[account(mut, close = owner, seeds = [treasury_wallet.key().as_ref(), withdraw_authorization.authority.as_ref()], bump)]
+    pub withdraw_authorization: Account<'info, WithdrawAuthorization>,
+    #[account(mut)]
+    pub proposal: Option<Account<'info, Proposal>>,
+}
+
+#[derive(Accounts)]
+pub struct Deposit<'info> {
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+    #[account()]
+    pub treasury_wallet: Account<'info, TreasuryWalletAccount>,
+    /// CHECK: account will never be validated, it's just used to sign transactions as the treasury wallet
+    #[account(seeds = [treasury_wallet.key().as_ref()], bump)]
+    pub treasury_authority: AccountInfo<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, token::mint = mint, token::authority = depositor)]
+    pub source: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, token::mint = mint, token::authority = treasury_authority)]
+    pub treasury_wallet_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(init_if_needed,
+    seeds = [b"ledger", treasury_wallet.key().as_ref(), mint.key().as_ref()],
+    bump, payer = depositor, space = MintLedger::space())]
+    pub mint_ledger: Account<'info, MintLedger>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct OwnerWithdraw<'info> {
+    pub owner: Signer<'info>,
+    #[account()]
+    pub treasury_wallet: Account<'info, TreasuryWalletAccount>,
+    /// CHECK: account will never be validated, it's just used to sign transactions as the treasury wallet
+    #[account(seeds = [treasury_wallet.key().as_ref()], bump)]
+    pub treasury_authority: AccountInfo<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, token::mint = mint, token::authority = treasury_authority)]
+    pub treasury_wallet_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(seeds = [b"ledger", treasury_wallet.key().as_ref(), mint.key().as_ref()], bump = mint_ledger.bump)]
+    pub mint_ledger: Account<'info, MintLedger>,
+    #[account(mut, token::mint = mint)]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub proposal: Option<Account<'info, Proposal>>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
 #[derive(Accounts)]
 pub struct Withdraw<'info> {
     #[account()]
@@ -106,22 +642,987 @@ pub struct Withdraw<'info> {
     #[account(mut)] // Validate that the treasury wallet is the owner
     pub treasury_wallet_token_account: InterfaceAccount<'info, TokenAccount>,
     pub mint: InterfaceAccount<'info, Mint>,
-    #[account(seeds = [treasury_wallet.key().as_ref(), authority.key().as_ref()], bump)]
+    #[account(mut, seeds = [treasury_wallet.key().as_ref(), authority.key().as_ref()], bump)]
     pub withdraw_authorization: Account<'info, WithdrawAuthorization>,
     /// CHECK: account will never be validated, it's just used to sign transactions as the treasury wallet
     #[account(seeds = [treasury_wallet.key().as_ref()], bump)]
     pub treasury_authority: AccountInfo<'info>,
+    /// CHECK: ledger of the mint, may not be initialized if nothing was ever reserved
+    #[account(mut, seeds = [b"ledger", treasury_wallet.key().as_ref(), mint.key().as_ref()], bump)]
+    pub mint_ledger: AccountInfo<'info>,
     #[account(mut)]
     pub destination: InterfaceAccount<'info, TokenAccount>,
-    pub token_program: Program<'info, Token2022>,
+    /// CHECK: owner of the destination token account, only needed for owner program constraints
+    pub destination_owner: Option<AccountInfo<'info>>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct Reserve<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub authority: Signer<'info>,
+    #[account()]
+    pub treasury_wallet: Account<'info, TreasuryWalletAccount>,
+    #[account(mut, seeds = [treasury_wallet.key().as_ref(), authority.key().as_ref()], bump)]
+    pub withdraw_authorization: Account<'info, WithdrawAuthorization>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(init_if_needed,
+    seeds = [b"ledger", treasury_wallet.key().as_ref(), mint.key().as_ref()],
+    bump, payer = payer, space = MintLedger::space())]
+    pub mint_ledger: Account<'info, MintLedger>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct GetCoverage<'info> {
+    #[account()]
+    pub treasury_wallet: Account<'info, TreasuryWalletAccount>,
+    /// CHECK: account will never be validated, it's just used to sign transactions as the treasury wallet
+    #[account(seeds = [treasury_wallet.key().as_ref()], bump)]
+    pub treasury_authority: AccountInfo<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(token::mint = mint, token::authority = treasury_authority)]
+    pub treasury_wallet_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(seeds = [b"ledger", treasury_wallet.key().as_ref(), mint.key().as_ref()], bump = mint_ledger.bump)]
+    pub mint_ledger: Account<'info, MintLedger>,
 }
 
 #[account]
 pub struct TreasuryWalletAccount {
     pub owner: Pubkey,
+    pub pending_owner: Option<Pubkey>,
+    // While threshold > 0 the owner key has no powers, owner actions go through proposals
+    pub signers: Vec<Pubkey>,
+    pub threshold: u8,
+    pub proposal_count: u64,
+}
+
+impl TreasuryWalletAccount {
+    pub fn space() -> usize {
+        8 + 32 + 33 + 4 + MAX_SIGNERS * 32 + 1 + 8
+    }
+
+    // Without a multisig owner actions are signed by the owner. With a multisig they are
+    // executed by one of the signers with a proposal for the action that met the threshold.
+    pub fn authorize_owner_action(
+        &self,
+        treasury_wallet: Pubkey,
+        signer: &Pubkey,
+        proposal: Option<&mut Proposal>,
+        action: &OwnerAction,
+        current_time: i64,
+    ) -> Result<()> {
+        if self.threshold == 0 {
+            require!(self.owner == *signer, TreasuryWalletError::Unauthorized);
+            return Ok(());
+        }
+        require!(
+            self.signers.contains(signer),
+            TreasuryWalletError::Unauthorized
+        );
+
+        let proposal = proposal.ok_or(TreasuryWalletError::InvalidProposal)?;
+        require!(
+            proposal.treasury_wallet == treasury_wallet && proposal.action == *action,
+            TreasuryWalletError::InvalidProposal
+        );
+        proposal.check_open(current_time)?;
+        require!(
+            proposal.approval_count(&self.signers) >= self.threshold as usize,
+            TreasuryWalletError::ThresholdNotMet
+        );
+
+        proposal.executed = true;
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, PartialEq, Eq)]
+pub enum OwnerAction {
+    SetMultisig {
+        signers: Vec<Pubkey>,
+        threshold: u8,
+    },
+    ProposeOwner {
+        new_owner: Option<Pubkey>,
+    },
+    AddWithdrawAuthorization {
+        authority: Pubkey,
+        limits: WithdrawalLimits,
+        valid_until: Option<i64>,
+        allowed_destinations: Vec<DestinationConstraint>,
+    },
+    SetWithdrawalLimits {
+        authority: Pubkey,
+        limits: WithdrawalLimits,
+    },
+    RevokeWithdrawAuthorization {
+        authority: Pubkey,
+    },
+    OwnerWithdraw {
+        mint: Pubkey,
+        amount: u64,
+        destination: Pubkey,
+    },
+    SetAllowedDestinations {
+        authority: Pubkey,
+        allowed_destinations: Vec<DestinationConstraint>,
+    },
+}
+
+impl OwnerAction {
+    pub fn space() -> usize {
+        1 + [
+            4 + MAX_SIGNERS * 32 + 1,
+            33,
+            32 + WithdrawalLimits::space() + 9 + 4 + MAX_ALLOWED_DESTINATIONS * 33,
+            32 + WithdrawalLimits::space(),
+            32,
+            32 + 8 + 32,
+            32 + 4 + MAX_ALLOWED_DESTINATIONS * 33,
+        ]
+        .into_iter()
+        .max()
+        .unwrap()
+    }
+}
+
+// Per mint accounting of the treasury, the balance itself is the one of the treasury token account
+#[account]
+pub struct MintLedger {
+    pub treasury_wallet: Pubkey,
+    pub mint: Pubkey,
+    // Funds earmarked for outstanding product obligations
+    pub reserved: u64,
+    pub bump: u8,
+}
+
+impl MintLedger {
+    pub fn space() -> usize {
+        8 + 32 + 32 + 8 + 1
+    }
+
+    pub fn free_balance(&self, balance: u64) -> u64 {
+        balance.saturating_sub(self.reserved)
+    }
+
+    pub fn coverage(&self, balance: u64) -> Coverage {
+        Coverage {
+            balance,
+            reserved: self.reserved,
+            free_balance: self.free_balance(balance),
+            coverage_in_basis_points: if self.reserved == 0 {
+                None
+            } else {
+                Some((balance as u128 * 10000 / self.reserved as u128).min(u64::MAX as u128) as u64)
+            },
+        }
+    }
+}
+
+// Coverage of the outstanding reservations by the balance of the treasury,
+// 10000 basis points means all reservations are funded
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Coverage {
+    pub balance: u64,
+    pub reserved: u64,
+    pub free_balance: u64,
+    pub coverage_in_basis_points: Option<u64>,
+}
+
+// OwnerProgram allows token accounts owned by accounts of a program,
+// e.g. the Payment PDAs of the structured product program
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DestinationConstraint {
+    TokenAccount { token_account: Pubkey },
+    Owner { owner: Pubkey },
+    OwnerProgram { program_id: Pubkey },
+}
+
+impl DestinationConstraint {
+    pub fn allows(
+        &self,
+        destination: &Pubkey,
+        destination_token_owner: &Pubkey,
+        destination_owner_program: Option<&Pubkey>,
+    ) -> bool {
+        match self {
+            DestinationConstraint::TokenAccount { token_account } => token_account == destination,
+            DestinationConstraint::Owner { owner } => owner == destination_token_owner,
+            DestinationConstraint::OwnerProgram { program_id } => {
+                destination_owner_program == Some(program_id)
+            }
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Reservation {
+    pub mint: Pubkey,
+    pub payment_date_offset: i64,
+    pub amount: u64,
+}
+
+#[account]
+pub struct Proposal {
+    pub treasury_wallet: Pubkey,
+    pub index: u64,
+    pub action: OwnerAction,
+    pub approvals: Vec<Pubkey>,
+    pub expires_at: i64,
+    pub executed: bool,
+    pub bump: u8,
+}
+
+impl Proposal {
+    pub fn space() -> usize {
+        8 + 32 + 8 + OwnerAction::space() + 4 + MAX_SIGNERS * 32 + 8 + 1 + 1
+    }
+
+    pub fn check_open(&self, current_time: i64) -> Result<()> {
+        require!(!self.executed, TreasuryWalletError::ProposalAlreadyExecuted);
+        require!(
+            current_time <= self.expires_at,
+            TreasuryWalletError::ProposalExpired
+        );
+        Ok(())
+    }
+
+    pub fn approve(&mut self, signer: Pubkey, current_time: i64) -> Result<()> {
+        self.check_open(current_time)?;
+        require!(
+            !self.approvals.contains(&signer),
+            TreasuryWalletError::AlreadyApproved
+        );
+        self.approvals.push(signer);
+        Ok(())
+    }
+
+    // Approvals of removed signers don't count
+    pub fn approval_count(&self, signers: &[Pubkey]) -> usize {
+        self.approvals
+            .iter()
+            .filter(|approval| signers.contains(approval))
+            .count()
+    }
+}
+
+// Caps on the amounts an authority can withdraw, amounts are in base units of the mint.
+// The lifetime and period limits count withdrawals of all mints together.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct WithdrawalLimits {
+    pub lifetime_limit: Option<u64>,
+    pub period_limit: Option<PeriodLimit>,
+    pub mint_limits: Vec<MintLimit>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeriodLimit {
+    pub period_length: i64,
+    pub limit: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MintLimit {
+    pub mint: Pubkey,
+    pub limit: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MintWithdrawal {
+    pub mint: Pubkey,
+    pub amount: u64,
+}
+
+impl WithdrawalLimits {
+    pub fn space() -> usize {
+        9 + 1 + 8 + 8 + 4 + MAX_MINT_LIMITS * (32 + 8)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            self.mint_limits.len() <= MAX_MINT_LIMITS,
+            TreasuryWalletError::InvalidWithdrawalLimits
+        );
+        require!(
+            self.mint_limits
+                .iter()
+                .enumerate()
+                .all(|(i, mint_limit)| self.mint_limits[..i]
+                    .iter()
+                    .all(|other| other.mint != mint_limit.mint)),
+            TreasuryWalletError::InvalidWithdrawalLimits
+        );
+        if let Some(period_limit) = self.period_limit {
+            require!(
+                period_limit.period_length > 0,
+                TreasuryWalletError::InvalidWithdrawalLimits
+            );
+        }
+        Ok(())
+    }
 }
 
 #[account]
 pub struct WithdrawAuthorization {
     pub authority: Pubkey,
+    pub limits: WithdrawalLimits,
+    pub valid_until: Option<i64>,
+    pub total_withdrawn: u64,
+    pub period_start: i64,
+    pub period_withdrawn: u64,
+    // Only withdrawals of mints with a mint limit are tallied per mint
+    pub mint_withdrawals: Vec<MintWithdrawal>,
+    pub reservations: Vec<Reservation>,
+    // Empty if withdrawals can go to any destination
+    pub allowed_destinations: Vec<DestinationConstraint>,
+}
+
+impl WithdrawAuthorization {
+    pub fn space() -> usize {
+        8 + 32
+            + WithdrawalLimits::space()
+            + 9
+            + 8
+            + 8
+            + 8
+            + 4
+            + MAX_MINT_LIMITS * (32 + 8)
+            + 4
+            + MAX_RESERVATIONS * (32 + 8 + 8)
+            + 4
+            + MAX_ALLOWED_DESTINATIONS * 33
+    }
+
+    pub fn check_destination(
+        &self,
+        destination: &Pubkey,
+        destination_token_owner: &Pubkey,
+        destination_owner_program: Option<&Pubkey>,
+    ) -> Result<()> {
+        require!(
+            self.allowed_destinations.is_empty()
+                || self.allowed_destinations.iter().any(|constraint| {
+                    constraint.allows(
+                        destination,
+                        destination_token_owner,
+                        destination_owner_program,
+                    )
+                }),
+            TreasuryWalletError::DestinationNotAllowed
+        );
+        Ok(())
+    }
+
+    pub fn add_reservation(
+        &mut self,
+        mint: Pubkey,
+        payment_date_offset: i64,
+        amount: u64,
+    ) -> Result<()> {
+        match self.reservations.iter_mut().find(|reservation| {
+            reservation.mint == mint && reservation.payment_date_offset == payment_date_offset
+        }) {
+            Some(reservation) => {
+                reservation.amount = reservation
+                    .amount
+                    .checked_add(amount)
+                    .ok_or(TreasuryWalletError::TooManyReservations)?;
+            }
+            None => {
+                require!(
+                    self.reservations.len() < MAX_RESERVATIONS,
+                    TreasuryWalletError::TooManyReservations
+                );
+                self.reservations.push(Reservation {
+                    mint,
+                    payment_date_offset,
+                    amount,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Consumes the reservations of the mint starting with the earliest payment date,
+    // returns the consumed amount
+    pub fn consume_reservations(&mut self, mint: Pubkey, amount: u64) -> u64 {
+        self.reservations
+            .sort_by_key(|reservation| reservation.payment_date_offset);
+        let mut consumed = 0;
+        for reservation in self
+            .reservations
+            .iter_mut()
+            .filter(|reservation| reservation.mint == mint)
+        {
+            let consume = reservation.amount.min(amount - consumed);
+            reservation.amount -= consume;
+            consumed += consume;
+        }
+        self.reservations
+            .retain(|reservation| reservation.amount > 0);
+        consumed
+    }
+
+    pub fn check_valid(&self, current_time: i64) -> Result<()> {
+        if let Some(valid_until) = self.valid_until {
+            require!(
+                current_time <= valid_until,
+                TreasuryWalletError::AuthorizationExpired
+            );
+        }
+        Ok(())
+    }
+
+    // Reserved funds are committed to payments, they are only released by paying them out
+    pub fn check_revocable(&self) -> Result<()> {
+        require!(
+            self.reservations.is_empty(),
+            TreasuryWalletError::OutstandingReservations
+        );
+        Ok(())
+    }
+
+    pub fn set_limits(&mut self, limits: WithdrawalLimits) {
+        self.mint_withdrawals.retain(|mint_withdrawal| {
+            limits
+                .mint_limits
+                .iter()
+                .any(|mint_limit| mint_limit.mint == mint_withdrawal.mint)
+        });
+        self.limits = limits;
+    }
+
+    // Adds the withdrawal to the tallies, fails if any limit would be exceeded.
+    // A period starts with the first withdrawal after the previous period ended.
+    pub fn record_withdrawal(
+        &mut self,
+        mint: Pubkey,
+        amount: u64,
+        current_time: i64,
+    ) -> Result<()> {
+        let total_withdrawn = self
+            .total_withdrawn
+            .checked_add(amount)
+            .ok_or(TreasuryWalletError::WithdrawalLimitExceeded)?;
+        if let Some(lifetime_limit) = self.limits.lifetime_limit {
+            require!(
+                total_withdrawn <= lifetime_limit,
+                TreasuryWalletError::WithdrawalLimitExceeded
+            );
+        }
+
+        if let Some(period_limit) = self.limits.period_limit {
+            if self.period_withdrawn == 0
+                || current_time >= self.period_start.saturating_add(period_limit.period_length)
+            {
+                self.period_start = current_time;
+                self.period_withdrawn = 0;
+            }
+            let period_withdrawn = self
+                .period_withdrawn
+                .checked_add(amount)
+                .ok_or(TreasuryWalletError::WithdrawalLimitExceeded)?;
+            require!(
+                period_withdrawn <= period_limit.limit,
+                TreasuryWalletError::WithdrawalLimitExceeded
+            );
+            self.period_withdrawn = period_withdrawn;
+        }
+
+        if let Some(mint_limit) = self.limits.mint_limits.iter().find(|l| l.mint == mint) {
+            let index = match self.mint_withdrawals.iter().position(|w| w.mint == mint) {
+                Some(index) => index,
+                None => {
+                    self.mint_withdrawals
+                        .push(MintWithdrawal { mint, amount: 0 });
+                    self.mint_withdrawals.len() - 1
+                }
+            };
+            let mint_withdrawn = self.mint_withdrawals[index]
+                .amount
+                .checked_add(amount)
+                .ok_or(TreasuryWalletError::WithdrawalLimitExceeded)?;
+            require!(
+                mint_withdrawn <= mint_limit.limit,
+                TreasuryWalletError::WithdrawalLimitExceeded
+            );
+            self.mint_withdrawals[index].amount = mint_withdrawn;
+        }
+
+        self.total_withdrawn = total_withdrawn;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn withdraw_authorization(limits: WithdrawalLimits) -> WithdrawAuthorization {
+        WithdrawAuthorization {
+            authority: Pubkey::new_unique(),
+            limits,
+            valid_until: None,
+            total_withdrawn: 0,
+            period_start: 0,
+            period_withdrawn: 0,
+            mint_withdrawals: Vec::new(),
+            reservations: Vec::new(),
+            allowed_destinations: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn record_withdrawal_without_limits() {
+        let mut authorization = withdraw_authorization(WithdrawalLimits::default());
+        authorization
+            .record_withdrawal(Pubkey::new_unique(), u64::MAX, 1000)
+            .unwrap();
+        assert_eq!(authorization.total_withdrawn, u64::MAX);
+        assert!(authorization.mint_withdrawals.is_empty());
+    }
+
+    #[test]
+    fn record_withdrawal_lifetime_limit() {
+        let mint = Pubkey::new_unique();
+        let mut authorization = withdraw_authorization(WithdrawalLimits {
+            lifetime_limit: Some(1000),
+            ..Default::default()
+        });
+        authorization.record_withdrawal(mint, 600, 1000).unwrap();
+        authorization.record_withdrawal(mint, 400, 1000).unwrap();
+        assert!(authorization.record_withdrawal(mint, 1, 1000).is_err());
+        assert_eq!(authorization.total_withdrawn, 1000);
+    }
+
+    #[test]
+    fn record_withdrawal_period_limit() {
+        let mint = Pubkey::new_unique();
+        let mut authorization = withdraw_authorization(WithdrawalLimits {
+            period_limit: Some(PeriodLimit {
+                period_length: 86400,
+                limit: 1000,
+            }),
+            ..Default::default()
+        });
+        authorization.record_withdrawal(mint, 1000, 1000).unwrap();
+        assert!(authorization.record_withdrawal(mint, 1, 87399).is_err());
+        authorization.record_withdrawal(mint, 500, 87400).unwrap();
+        assert_eq!(authorization.period_start, 87400);
+        assert_eq!(authorization.period_withdrawn, 500);
+        assert_eq!(authorization.total_withdrawn, 1500);
+    }
+
+    #[test]
+    fn record_withdrawal_mint_limit() {
+        let limited_mint = Pubkey::new_unique();
+        let other_mint = Pubkey::new_unique();
+        let mut authorization = withdraw_authorization(WithdrawalLimits {
+            mint_limits: vec![MintLimit {
+                mint: limited_mint,
+                limit: 1000,
+            }],
+            ..Default::default()
+        });
+        authorization
+            .record_withdrawal(limited_mint, 700, 1000)
+            .unwrap();
+        authorization
+            .record_withdrawal(other_mint, 5000, 1000)
+            .unwrap();
+        assert!(authorization
+            .record_withdrawal(limited_mint, 301, 1000)
+            .is_err());
+        authorization
+            .record_withdrawal(limited_mint, 300, 1000)
+            .unwrap();
+        assert_eq!(
+            authorization.mint_withdrawals,
+            vec![MintWithdrawal {
+                mint: limited_mint,
+                amount: 1000
+            }]
+        );
+        assert_eq!(authorization.total_withdrawn, 6000);
+    }
+
+    #[test]
+    fn set_limits_keeps_tally_of_limited_mints() {
+        let mint_1 = Pubkey::new_unique();
+        let mint_2 = Pubkey::new_unique();
+        let mut authorization = withdraw_authorization(WithdrawalLimits {
+            mint_limits: vec![
+                MintLimit {
+                    mint: mint_1,
+                    limit: 1000,
+                },
+                MintLimit {
+                    mint: mint_2,
+                    limit: 1000,
+                },
+            ],
+            ..Default::default()
+        });
+        authorization.record_withdrawal(mint_1, 100, 1000).unwrap();
+        authorization.record_withdrawal(mint_2, 200, 1000).unwrap();
+        authorization.set_limits(WithdrawalLimits {
+            mint_limits: vec![MintLimit {
+                mint: mint_2,
+                limit: 250,
+            }],
+            ..Default::default()
+        });
+        assert_eq!(
+            authorization.mint_withdrawals,
+            vec![MintWithdrawal {
+                mint: mint_2,
+                amount: 200
+            }]
+        );
+        assert!(authorization.record_withdrawal(mint_2, 51, 1000).is_err());
+    }
+
+    #[test]
+    fn check_valid_until() {
+        let mut authorization = withdraw_authorization(WithdrawalLimits::default());
+        assert!(authorization.check_valid(i64::MAX).is_ok());
+        authorization.valid_until = Some(1000);
+        assert!(authorization.check_valid(1000).is_ok());
+        assert!(authorization.check_valid(1001).is_err());
+    }
+
+    fn multisig_treasury_wallet(signers: Vec<Pubkey>, threshold: u8) -> TreasuryWalletAccount {
+        TreasuryWalletAccount {
+            owner: Pubkey::new_unique(),
+            pending_owner: None,
+            signers,
+            threshold,
+            proposal_count: 0,
+        }
+    }
+
+    fn proposal(treasury_wallet: Pubkey, action: OwnerAction, approvals: Vec<Pubkey>) -> Proposal {
+        Proposal {
+            treasury_wallet,
+            index: 0,
+            action,
+            approvals,
+            expires_at: 1000,
+            executed: false,
+            bump: 255,
+        }
+    }
+
+    #[test]
+    fn authorize_owner_action_without_multisig() {
+        let treasury_wallet = multisig_treasury_wallet(Vec::new(), 0);
+        let action = OwnerAction::ProposeOwner { new_owner: None };
+        assert!(treasury_wallet
+            .authorize_owner_action(
+                Pubkey::new_unique(),
+                &treasury_wallet.owner,
+                None,
+                &action,
+                0
+            )
+            .is_ok());
+        assert!(treasury_wallet
+            .authorize_owner_action(
+                Pubkey::new_unique(),
+                &Pubkey::new_unique(),
+                None,
+                &action,
+                0
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn authorize_owner_action_with_multisig() {
+        let signers = vec![
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        ];
+        let treasury_wallet_key = Pubkey::new_unique();
+        let treasury_wallet = multisig_treasury_wallet(signers.clone(), 2);
+        let action = OwnerAction::RevokeWithdrawAuthorization {
+            authority: Pubkey::new_unique(),
+        };
+
+        // the owner lost its powers
+        assert!(treasury_wallet
+            .authorize_owner_action(
+                treasury_wallet_key,
+                &treasury_wallet.owner,
+                None,
+                &action,
+                0
+            )
+            .is_err());
+
+        let mut pending = proposal(treasury_wallet_key, action.clone(), vec![signers[0]]);
+        assert!(treasury_wallet
+            .authorize_owner_action(
+                treasury_wallet_key,
+                &signers[0],
+                Some(&mut pending),
+                &action,
+                0
+            )
+            .is_err());
+
+        pending.approve(signers[1], 0).unwrap();
+        let other_action = OwnerAction::RevokeWithdrawAuthorization {
+            authority: Pubkey::new_unique(),
+        };
+        assert!(treasury_wallet
+            .authorize_owner_action(
+                treasury_wallet_key,
+                &signers[2],
+                Some(&mut pending),
+                &other_action,
+                0
+            )
+            .is_err());
+        assert!(treasury_wallet
+            .authorize_owner_action(
+                Pubkey::new_unique(),
+                &signers[2],
+                Some(&mut pending),
+                &action,
+                0
+            )
+            .is_err());
+        assert!(treasury_wallet
+            .authorize_owner_action(
+                treasury_wallet_key,
+                &signers[2],
+                Some(&mut pending),
+                &action,
+                1001
+            )
+            .is_err());
+        treasury_wallet
+            .authorize_owner_action(
+                treasury_wallet_key,
+                &signers[2],
+                Some(&mut pending),
+                &action,
+                1000,
+            )
+            .unwrap();
+        assert!(pending.executed);
+
+        // proposals can only be executed once
+        assert!(treasury_wallet
+            .authorize_owner_action(
+                treasury_wallet_key,
+                &signers[2],
+                Some(&mut pending),
+                &action,
+                0
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn approve_proposal() {
+        let signers = vec![Pubkey::new_unique(), Pubkey::new_unique()];
+        let action = OwnerAction::ProposeOwner { new_owner: None };
+        let mut pending = proposal(Pubkey::new_unique(), action, vec![signers[0]]);
+        assert!(pending.approve(signers[0], 0).is_err());
+        assert!(pending.approve(signers[1], 1001).is_err());
+        pending.approve(signers[1], 1000).unwrap();
+        assert_eq!(pending.approval_count(&signers), 2);
+        assert_eq!(pending.approval_count(&signers[1..]), 1);
+    }
+
+    #[test]
+    fn mint_ledger_free_balance() {
+        let mut mint_ledger = MintLedger {
+            treasury_wallet: Pubkey::new_unique(),
+            mint: Pubkey::new_unique(),
+            reserved: 0,
+            bump: 255,
+        };
+        assert_eq!(mint_ledger.free_balance(1000), 1000);
+        mint_ledger.reserved = 400;
+        assert_eq!(mint_ledger.free_balance(1000), 600);
+        assert_eq!(mint_ledger.free_balance(300), 0);
+    }
+
+    #[test]
+    fn consume_reservations_earliest_first() {
+        let mint = Pubkey::new_unique();
+        let other_mint = Pubkey::new_unique();
+        let mut authorization = withdraw_authorization(WithdrawalLimits::default());
+        authorization.add_reservation(mint, 2000, 500).unwrap();
+        authorization.add_reservation(other_mint, 500, 700).unwrap();
+        authorization.add_reservation(mint, 1000, 300).unwrap();
+        authorization.add_reservation(mint, 1000, 100).unwrap();
+
+        assert_eq!(authorization.consume_reservations(mint, 600), 600);
+        assert_eq!(
+            authorization.reservations,
+            vec![
+                Reservation {
+                    mint: other_mint,
+                    payment_date_offset: 500,
+                    amount: 700
+                },
+                Reservation {
+                    mint,
+                    payment_date_offset: 2000,
+                    amount: 300
+                },
+            ]
+        );
+        assert_eq!(authorization.consume_reservations(mint, 1000), 300);
+        assert_eq!(authorization.consume_reservations(mint, 1000), 0);
+        assert_eq!(authorization.reservations.len(), 1);
+    }
+
+    #[test]
+    fn check_revocable_rejects_outstanding_reservations() {
+        let mint = Pubkey::new_unique();
+        let mut authorization = withdraw_authorization(WithdrawalLimits::default());
+        authorization.check_revocable().unwrap();
+        authorization.add_reservation(mint, 1000, 500).unwrap();
+        authorization.add_reservation(mint, 2000, 500).unwrap();
+        assert!(authorization.check_revocable().is_err());
+        // partially paid out reservations are still outstanding
+        authorization.consume_reservations(mint, 700);
+        assert!(authorization.check_revocable().is_err());
+        authorization.consume_reservations(mint, 300);
+        authorization.check_revocable().unwrap();
+    }
+
+    #[test]
+    fn add_reservation_rejects_too_many_reservations() {
+        let mint = Pubkey::new_unique();
+        let mut authorization = withdraw_authorization(WithdrawalLimits::default());
+        for payment_date_offset in 0..MAX_RESERVATIONS as i64 {
+            authorization
+                .add_reservation(mint, payment_date_offset, 1)
+                .unwrap();
+        }
+        assert!(authorization
+            .add_reservation(mint, MAX_RESERVATIONS as i64, 1)
+            .is_err());
+        // existing reservations can still be increased
+        authorization.add_reservation(mint, 0, 1).unwrap();
+        assert_eq!(authorization.reservations[0].amount, 2);
+    }
+
+    #[test]
+    fn mint_ledger_coverage() {
+        let mut mint_ledger = MintLedger {
+            treasury_wallet: Pubkey::new_unique(),
+            mint: Pubkey::new_unique(),
+            reserved: 0,
+            bump: 255,
+        };
+        assert_eq!(mint_ledger.coverage(1000).coverage_in_basis_points, None);
+        mint_ledger.reserved = 800;
+        assert_eq!(
+            mint_ledger.coverage(1000),
+            Coverage {
+                balance: 1000,
+                reserved: 800,
+                free_balance: 200,
+                coverage_in_basis_points: Some(12500),
+            }
+        );
+        assert_eq!(
+            mint_ledger.coverage(400).coverage_in_basis_points,
+            Some(5000)
+        );
+    }
+
+    #[test]
+    fn check_destination() {
+        let destination = Pubkey::new_unique();
+        let destination_token_owner = Pubkey::new_unique();
+        let program_id = Pubkey::new_unique();
+        let mut authorization = withdraw_authorization(WithdrawalLimits::default());
+        assert!(authorization
+            .check_destination(&destination, &destination_token_owner, None)
+            .is_ok());
+
+        authorization.allowed_destinations = vec![DestinationConstraint::TokenAccount {
+            token_account: destination,
+        }];
+        assert!(authorization
+            .check_destination(&destination, &destination_token_owner, None)
+            .is_ok());
+        assert!(authorization
+            .check_destination(&Pubkey::new_unique(), &destination_token_owner, None)
+            .is_err());
+
+        authorization.allowed_destinations = vec![DestinationConstraint::Owner {
+            owner: destination_token_owner,
+        }];
+        assert!(authorization
+            .check_destination(&Pubkey::new_unique(), &destination_token_owner, None)
+            .is_ok());
+        assert!(authorization
+            .check_destination(&destination, &Pubkey::new_unique(), None)
+            .is_err());
+
+        authorization.allowed_destinations = vec![
+            DestinationConstraint::TokenAccount {
+                token_account: Pubkey::new_unique(),
+            },
+            DestinationConstraint::OwnerProgram { program_id },
+        ];
+        assert!(authorization
+            .check_destination(&destination, &destination_token_owner, Some(&program_id))
+            .is_ok());
+        assert!(authorization
+            .check_destination(&destination, &destination_token_owner, None)
+            .is_err());
+        assert!(authorization
+            .check_destination(
+                &destination,
+                &destination_token_owner,
+                Some(&Pubkey::new_unique())
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn validate_withdrawal_limits() {
+        let mint = Pubkey::new_unique();
+        assert!(WithdrawalLimits::default().validate().is_ok());
+        assert!(WithdrawalLimits {
+            period_limit: Some(PeriodLimit {
+                period_length: 0,
+                limit: 1000,
+            }),
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+        assert!(WithdrawalLimits {
+            mint_limits: vec![MintLimit { mint, limit: 1 }, MintLimit { mint, limit: 2 }],
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+        assert!(WithdrawalLimits {
+            mint_limits: vec![
+                MintLimit {
+                    mint: Pubkey::new_unique(),
+                    limit: 1
+                };
+                MAX_MINT_LIMITS + 1
+            ],
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+    }
 }
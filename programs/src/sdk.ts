@@ -50,6 +50,7 @@ export type IssueInstructionAccounts = {
   issuer: PublicKey;
   issuerTreasuryWallet: PublicKey;
   mint: PublicKey;
+  legalApprover?: PublicKey;
 };
 
 export type PayIssuanceInstructionAccounts = {
@@ -85,6 +86,20 @@ export type CreateSettlePaymentInstructionAccounts = {
   paymentMint: PublicKey;
 };
 
+export type BrcPriceAuthorityInstructionAccounts = {
+  mint: PublicKey;
+  oracle: PublicKey;
+  quoteCurrencyMint: PublicKey;
+  paymentMint: PublicKey;
+  deliveryMint?: PublicKey;
+};
+
+// anchor encoding of the brc price authority oracle sources
+export type OracleSource =
+  | { dummyOracle: Record<string, never> }
+  | { pyth: Record<string, never> }
+  | { aggregatedOracle: Record<string, never> };
+
 export type SignStructuredProductInitOfflineConfig = {
   investor: PublicKey;
   issuer: PublicKey;
@@ -98,10 +113,17 @@ export type SignStructuredProductInitOfflineConfig = {
   dummyOracle: PublicKey;
   underlyingSymbol: string;
   paymentMint: PublicKey;
+  quoteCurrencyMint: PublicKey;
   initialPrincipal: BN;
   initialFixingPrice: BN;
+  priceExponent: number;
   barrierInBasisPoints: BN;
+  continuousBarrier: boolean;
+  maxStaleness: BN;
+  fixingWindow: BN;
   supply: BN;
+  offerExpiry: BN | null;
+  legalApprover: PublicKey | null;
 };
 
 export type SignStructuredProductIssueOffline = {
@@ -263,7 +285,9 @@ export class StructuredNotesSdk {
     mint: Keypair,
     maxSnapshots: number,
     issuancePricePerUnit: BN,
-    supply: BN
+    supply: BN,
+    offerExpiry: BN | null = null,
+    legalApprover: PublicKey | null = null
   ) {
     const structuredProductPDA = getPdaWithSeeds(
       [mint.publicKey.toBuffer()],
@@ -296,21 +320,33 @@ export class StructuredNotesSdk {
       systemProgram: anchor.web3.SystemProgram.programId,
     };
     return await this.program.methods
-      .initialize(maxSnapshots, issuancePricePerUnit, supply)
+      .initialize(
+        maxSnapshots,
+        issuancePricePerUnit,
+        supply,
+        offerExpiry,
+        legalApprover
+      )
       .accounts(allAccounts)
       .signers([mint])
       .instruction();
   }
 
   async createBrcPriceAuthorityInstruction(
-    accounts: { mint: PublicKey; dummyOracle: PublicKey },
+    accounts: BrcPriceAuthorityInstructionAccounts,
     underlyingSymbol: string,
     principalPaymentDateOffset: BN,
     initialPrincipal: BN,
     initialFixingPrice: BN,
-    barrierInBasisPoints: BN
+    priceExponent: number,
+    barrierInBasisPoints: BN,
+    continuousBarrier: boolean,
+    maxStaleness: BN,
+    fixingWindow: BN,
+    oracleSource: OracleSource = { dummyOracle: {} }
   ) {
-    const { mint, dummyOracle } = accounts;
+    const { mint, oracle, quoteCurrencyMint, paymentMint, deliveryMint } =
+      accounts;
     const paymentPda = await this.getPaymentPda(
       mint,
       true,
@@ -333,14 +369,22 @@ export class StructuredNotesSdk {
         principalPaymentDateOffset,
         initialPrincipal,
         initialFixingPrice,
-        barrierInBasisPoints
+        priceExponent,
+        barrierInBasisPoints,
+        continuousBarrier,
+        maxStaleness,
+        fixingWindow,
+        oracleSource
       )
       .accounts({
         authority: this.provider.publicKey,
         structuredProduct: structuredProductPDA.publicKey,
         payment: paymentPda.publicKey,
         brc: brcPDA.publicKey,
-        dummyOracle,
+        oracle,
+        quoteCurrencyMint,
+        paymentMint,
+        deliveryMint: deliveryMint ?? null,
         dummyOracleProgram: this.dummyOracleProgram.programId,
         structuredProductProgram: this.program.programId,
         systemProgram: SystemProgram.programId,
@@ -565,12 +609,14 @@ export class StructuredNotesSdk {
       this.transferSnapshotHookProgram.programId
     );
 
+    // no static payments are reserved in the treasury wallet
     return await this.program.methods
-      .issue()
+      .issue([])
       .accounts({
         mint: accounts.mint,
         issuer: accounts.issuer,
         investor: accounts.investor,
+        legalApprover: accounts.legalApprover ?? null,
         structuredProduct: structuredProductPDA.publicKey,
         snapshotConfig: snapshotConfigPDA.publicKey,
         investorTokenAccount: investorATA,
@@ -580,6 +626,11 @@ export class StructuredNotesSdk {
         programTokenSnapshotBalancesAccount:
           programSnapshotTokenBalancesAcountPDA.publicKey,
         extraAccountMetaList: extraAccountMetasPDA.publicKey,
+        treasuryWallet: null,
+        withdrawalAuthorization: null,
+        reservationMint: null,
+        mintLedger: null,
+        treasuryWalletProgram: null,
         snapshotTransferHookProgram: this.transferSnapshotHookProgram.programId,
         tokenProgram: TOKEN_2022_PROGRAM_ID,
         associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
@@ -592,7 +643,7 @@ export class StructuredNotesSdk {
   async createSetPaymentPriceInstruction(
     underlyingSymbol: string,
     mint: PublicKey,
-    dummyOracle: PublicKey,
+    oracle: PublicKey,
     paymentDateOffsetSeconds: BN
   ) {
     const structuredProductPDA = getPdaWithSeeds(
@@ -618,7 +669,7 @@ export class StructuredNotesSdk {
         brc: brcPDA.publicKey,
         structuredProduct: structuredProductPDA.publicKey,
         payment: paymentPDA.publicKey,
-        dummyOracle: dummyOracle,
+        oracle,
         structuredProductProgram: this.program.programId,
         dummyOracleProgram: this.dummyOracleProgram.programId,
       })
@@ -654,6 +705,15 @@ export class StructuredNotesSdk {
       this.treasuryWalletProgram.programId
     );
 
+    const mintLedgerPDA = getPdaWithSeeds(
+      [
+        Buffer.from("ledger"),
+        pullPaymentAccounts.treasuryWallet.toBuffer(),
+        pullPaymentAccounts.paymentMint.toBuffer(),
+      ],
+      this.treasuryWalletProgram.programId
+    );
+
     return await this.program.methods
       .pullPayment(new BN(paymentTimestamp))
       .accounts({
@@ -668,6 +728,7 @@ export class StructuredNotesSdk {
           TOKEN_2022_PROGRAM_ID,
           ASSOCIATED_TOKEN_PROGRAM_ID
         ),
+        mintLedger: mintLedgerPDA.publicKey,
         mint: pullPaymentAccounts.structuredProductMint,
         structuredProduct: structuredProductPDA.publicKey,
         paymentMint: pullPaymentAccounts.paymentMint,
@@ -754,9 +815,18 @@ export class StructuredNotesSdk {
       .instruction();
   }
 
+  getPriceHistoryPda(dummyOracle: PublicKey) {
+    return getPdaWithSeeds(
+      [Buffer.from("history"), dummyOracle.toBuffer()],
+      this.dummyOracleProgram.programId
+    );
+  }
+
+  // The price is given in 10^exponent units of the quote currency
   async createInitDummyOracleInstruction(
     assetSymbol: string,
     price: BN,
+    exponent: number,
     quoteCurrencyMint: PublicKey // payment mint
   ) {
     const dummyOraclePda = getPdaWithSeeds(
@@ -765,9 +835,11 @@ export class StructuredNotesSdk {
     );
 
     return await this.dummyOracleProgram.methods
-      .initialize(assetSymbol, price)
+      .initialize(assetSymbol, price, exponent)
       .accounts({
         dummyOracle: dummyOraclePda.publicKey,
+        priceHistory: this.getPriceHistoryPda(dummyOraclePda.publicKey)
+          .publicKey,
         authority: this.provider.publicKey,
         systemProgram: SystemProgram.programId,
         quoteCurrencyMint,
@@ -785,6 +857,8 @@ export class StructuredNotesSdk {
       .updatePrice(assetSymbol, price)
       .accounts({
         dummyOracle: dummyOraclePda.publicKey,
+        priceHistory: this.getPriceHistoryPda(dummyOraclePda.publicKey)
+          .publicKey,
         authority: this.provider.publicKey,
       })
       .instruction();
@@ -892,20 +966,28 @@ export class StructuredNotesSdk {
       mint,
       config.payments.length - 1, // principal uses same snapshot as last coupon,
       config.initialPrincipal, // this is the principal amount,
-      config.supply
+      config.supply,
+      config.offerExpiry,
+      config.legalApprover
     );
 
     const addBrcPriceAuthorityIx =
       await this.createBrcPriceAuthorityInstruction(
         {
           mint: mint.publicKey,
-          dummyOracle: config.dummyOracle,
+          oracle: config.dummyOracle,
+          quoteCurrencyMint: config.quoteCurrencyMint,
+          paymentMint: config.payments[config.payments.length - 1].paymentMint,
         },
         config.underlyingSymbol,
         config.payments[config.payments.length - 1].paymentDateOffsetSeconds,
         config.initialPrincipal,
         config.initialFixingPrice,
-        config.barrierInBasisPoints
+        config.priceExponent,
+        config.barrierInBasisPoints,
+        config.continuousBarrier,
+        config.maxStaleness,
+        config.fixingWindow
       );
 
     const brcPDA = getPdaWithSeeds(
@@ -927,13 +1009,19 @@ export class StructuredNotesSdk {
         )
       )
     );
+    // payments are only pulled into token accounts of the structured product's payments
     const addAuthorizationIx = await this.treasuryWalletProgram.methods
-      .addWithdrawAuthorization()
+      .addWithdrawAuthorization(
+        { lifetimeLimit: null, periodLimit: null, mintLimits: [] },
+        null,
+        [{ ownerProgram: { programId: this.program.programId } }]
+      )
       .accounts({
         owner: config.issuer,
         treasuryWallet: config.issuerTreasuryWallet,
         authority: structuredProductPDA.publicKey,
         withdrawAuthorization: withdrawAuthorizationPDA.publicKey,
+        proposal: null,
         rent: SYSVAR_RENT_PUBKEY,
         systemProgram: SystemProgram.programId,
       })
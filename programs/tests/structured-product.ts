@@ -71,6 +71,8 @@ describe("structured-product", () => {
   const supply: BN = new BN(1);
   const initialPrincipal: BN = new BN(100000);
   const initialFixingPrice: BN = new BN(42000);
+  // fixing prices are in whole units of the payment mint
+  const priceExponent = 0;
   const barrierInBasisPoints: BN = new BN(8000);
   const finalFixingPrice: BN = new BN(33600);
   const expectedFinalPrincipal: BN = new BN(80000);
@@ -197,10 +199,12 @@ describe("structured-product", () => {
     );
 
     const initDummyOracleIx = await dummyOracleProgram.methods
-      .initialize("BTC", initialFixingPrice)
+      .initialize("BTC", initialFixingPrice, priceExponent)
       .accounts({
         authority: issuer.publicKey,
         dummyOracle: dummyOraclePDA.publicKey,
+        priceHistory: sdk.getPriceHistoryPda(dummyOraclePDA.publicKey)
+          .publicKey,
         systemProgram: anchor.web3.SystemProgram.programId,
         quoteCurrencyMint: paymentMint.publicKey,
       })
@@ -250,7 +254,12 @@ describe("structured-product", () => {
       dummyOracle: dummyOraclePDA.publicKey,
       underlyingSymbol: "BTC",
       barrierInBasisPoints,
+      continuousBarrier: false,
       initialFixingPrice,
+      priceExponent,
+      // the final fixing price is set right after the payment date
+      maxStaleness: new BN(60),
+      fixingWindow: new BN(60),
       payments: [
         {
           principal: false,
@@ -272,8 +281,11 @@ describe("structured-product", () => {
         },
       ],
       paymentMint: paymentMint.publicKey,
+      quoteCurrencyMint: paymentMint.publicKey,
       initialPrincipal,
       supply,
+      offerExpiry: null,
+      legalApprover: null,
     };
     /*** ----------------- BACKEND ----------------- ***/
     // Inputs assumed to be given by investor and random yield provided by backend
@@ -205,14 +205,21 @@ describe("treasury-wallet", () => {
       bump: _withDrawAuthorizationBump,
     };
 
+    // no withdrawal limits and no expiry, withdrawals only to the authority's token account
     const addWithdrawAuthorityTx = new Transaction().add(
       await program.methods
-        .addWithdrawAuthorization()
+        .addWithdrawAuthorization(
+          { lifetimeLimit: null, periodLimit: null, mintLimits: [] },
+          null,
+          [{ tokenAccount: { tokenAccount: withdrawAuthorityATA } }]
+        )
         .accounts({
           owner: owner.publicKey,
           treasuryWallet: treasuryWallet.publicKey,
           withdrawAuthorization: withdrawAuthorizationPda.publicKey,
           authority: withdrawAuthority.publicKey,
+          proposal: null,
+          rent: anchor.web3.SYSVAR_RENT_PUBKEY,
           systemProgram: anchor.web3.SystemProgram.programId,
         })
         .instruction()
@@ -231,6 +238,15 @@ describe("treasury-wallet", () => {
       `added withdraw authority: ${withdrawAuthority.publicKey.toBase58()}`
     );
 
+    const [mintLedgerPubkey] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("ledger"),
+        treasuryWallet.publicKey.toBuffer(),
+        mint.publicKey.toBuffer(),
+      ],
+      program.programId
+    );
+
     const withdrawTx = new Transaction().add(
       createAssociatedTokenAccountInstruction(
         withdrawAuthority.publicKey,
@@ -249,7 +265,9 @@ describe("treasury-wallet", () => {
           treasuryWalletTokenAccount: treasuryWalletATA,
           treasuryAuthority: treasuryWalletAuthorityPda.publicKey,
           withdrawAuthorization: withdrawAuthorizationPda.publicKey,
+          mintLedger: mintLedgerPubkey,
           destination: withdrawAuthorityATA,
+          destinationOwner: null,
           tokenProgram: TOKEN_PROGRAM_ID,
         })
         .instruction()